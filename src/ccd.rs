@@ -0,0 +1,417 @@
+use bevy::prelude::*;
+
+use crate::{
+    collision_layers::{CollisionLayers, layers_interact},
+    collisions::{Collider, Shape},
+    flat_aabb::FlatAABB,
+    flat_body::{FlatBody, FlatBodyType},
+    gjk::{closest_points, convex_core, gjk_collide, shape_distance},
+    helpers::to_vec2,
+    one_way_platform::{DropThrough, OneWayPlatform, lands_on, platform_up},
+};
+
+const MAX_CCD_ITERATIONS: usize = 20;
+/// Conservative advancement stops once the shapes are closer than this.
+const CCD_TOLERANCE: f32 = 0.25;
+/// How deep a body is placed into the surface it hit.
+const CCD_CONTACT_DEPTH: f32 = 0.5;
+/// Slower bodies, as part of their smallest extent per sub step, skip ccd.
+const CCD_MOTION_THRESHOLD: f32 = 0.25;
+
+/// Time of impact as a fraction of the sub step.
+pub struct TimeOfImpact {
+    pub time: f32,
+    pub normal: Vec2,
+    pub distance: f32,
+    /// Closest point on the surface of the body that was hit.
+    pub point: Vec2,
+}
+
+/// Largest distance of the shape from the body origin.
+fn bounding_radius(shape: &Shape) -> f32 {
+    let aabb = shape.get_aabb(&Transform::IDENTITY);
+    return aabb.min.abs().max(aabb.max.abs()).length();
+}
+
+/// Body pose after moving it by `t` of `translation` and `rotation` radians.
+fn interpolate(start: &Transform, translation: Vec2, rotation: f32, t: f32) -> Transform {
+    let mut transform = *start;
+    transform.translation += (translation * t).extend(0.);
+    transform.rotate_z(rotation * t);
+    return transform;
+}
+
+/// Conservative advancement of `shape` against a resting shape.
+pub fn time_of_impact(
+    shape: &Shape,
+    start: &Transform,
+    translation: Vec2,
+    rotation: f32,
+    other_shape: &Shape,
+    other_transform: &Transform,
+) -> Option<TimeOfImpact> {
+    if translation.length() + rotation.abs() <= f32::EPSILON {
+        return None;
+    }
+    let rotation_bound = rotation.abs() * bounding_radius(shape);
+
+    let mut closest = closest_points(shape, start, other_shape, other_transform)?;
+    if closest.distance <= CCD_TOLERANCE {
+        return None;
+    }
+
+    let mut time = 0.;
+    for _iteration in 0..MAX_CCD_ITERATIONS {
+        // only motion along the normal closes the gap, the shapes can not
+        // get closer than `motion_bound * dt` in `dt`, so stepping by this
+        // never skips over the contact
+        let motion_bound = translation.dot(closest.normal) + rotation_bound;
+        if motion_bound <= f32::EPSILON {
+            return None;
+        }
+        let next_time = time + (closest.distance - CCD_TOLERANCE * 0.5) / motion_bound;
+        if next_time >= 1. {
+            return None;
+        }
+
+        let transform = interpolate(start, translation, rotation, next_time);
+        let Some(next_closest) = closest_points(shape, &transform, other_shape, other_transform)
+        else {
+            break;
+        };
+        time = next_time;
+        closest = next_closest;
+        if closest.distance <= CCD_TOLERANCE {
+            break;
+        }
+    }
+
+    // converged, or the last pose known to be apart when it did not
+    return Some(TimeOfImpact {
+        time,
+        normal: closest.normal,
+        distance: closest.distance,
+        point: closest.point_b,
+    });
+}
+
+/// Direction to `other_shape` when the shapes already touch at `start`.
+pub fn touching_normal(
+    shape: &Shape,
+    start: &Transform,
+    other_shape: &Shape,
+    other_transform: &Transform,
+) -> Option<Vec2> {
+    for (_index, local, sub_shape) in shape.sub_shapes() {
+        let Some(core) = convex_core(&sub_shape, &start.mul_transform(local)) else {
+            continue;
+        };
+        for (_other_index, other_local, other_sub_shape) in other_shape.sub_shapes() {
+            let Some(other_core) = convex_core(
+                &other_sub_shape,
+                &other_transform.mul_transform(other_local),
+            ) else {
+                continue;
+            };
+            // closest points of overlapping cores say nothing about the normal
+            if let Some(collision) = gjk_collide(&core, &other_core) {
+                return Some(collision.collision_normal);
+            }
+        }
+    }
+
+    let (distance, normal) = shape_distance(shape, start, other_shape, other_transform)?;
+    if distance > CCD_TOLERANCE || normal == Vec2::ZERO {
+        return None;
+    }
+    return Some(normal);
+}
+
+/// Moves a fast body back to its first hit against static bodies.
+pub fn continuous_collision(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
+    platforms: &Query<'_, '_, &OneWayPlatform>,
+    drop_through: &Query<'_, '_, (), With<DropThrough>>,
+    entity: Entity,
+    start: &Transform,
+    delta_time: f32,
+) {
+    let Ok((_entity, transform, flat_body, collider)) = query.get(entity) else {
+        return;
+    };
+
+    if collider.sensor {
+        return;
+    }
+
+    let shape = &collider.shape;
+    let mut translation = to_vec2(&transform.translation) - to_vec2(&start.translation);
+    let rotation = flat_body.angular_velocity * delta_time;
+
+    let local_aabb = shape.get_aabb(&Transform::IDENTITY);
+    let min_extent = (local_aabb.max - local_aabb.min).min_element();
+    let motion_bound = translation.length() + rotation.abs() * bounding_radius(shape);
+    if motion_bound < min_extent * CCD_MOTION_THRESHOLD {
+        return;
+    }
+
+    let swept_aabb: FlatAABB = shape
+        .get_aabb(start)
+        .union(&shape.get_aabb(transform))
+        .expanded(rotation.abs() * bounding_radius(shape));
+
+    let mut others = Vec::new();
+    for (other, other_transform, other_body, other_collider) in query.iter() {
+        if other == entity
+            || !matches!(other_body.body_type, FlatBodyType::Static)
+            || other_collider.sensor
+        {
+            continue;
+        }
+        if !layers_interact(layers, entity, other) {
+            continue;
+        }
+        if !swept_aabb.overlaps(&other_collider.shape.get_aabb(other_transform)) {
+            continue;
+        }
+        // bodies only land on platforms while moving against their up
+        if let Ok(platform) = platforms.get(other)
+            && (drop_through.contains(entity)
+                || translation.dot(platform_up(platform, other_transform)) >= 0.)
+        {
+            continue;
+        }
+        others.push(other);
+    }
+
+    // the sweep can not see a body pass through what it touches at the start,
+    // the solver may have left it moving that way, so that motion is dropped
+    let mut touching = Vec::new();
+    let mut clipped = false;
+    for other in others.iter() {
+        let Ok((_, other_transform, _, other_collider)) = query.get(*other) else {
+            continue;
+        };
+        let Some(normal) = touching_normal(shape, start, &other_collider.shape, other_transform)
+        else {
+            continue;
+        };
+        touching.push(*other);
+        // a body halfway through a platform keeps passing through
+        if let Ok(platform) = platforms.get(*other)
+            && !lands_on(platform, other_transform, -normal)
+        {
+            continue;
+        }
+        let into_other = translation.dot(normal);
+        if into_other > 0. {
+            translation -= normal * into_other;
+            clipped = true;
+        }
+    }
+
+    let mut first_hit: Option<TimeOfImpact> = None;
+    for other in others.iter().filter(|other| !touching.contains(other)) {
+        let Ok((_, other_transform, _, other_collider)) = query.get(*other) else {
+            continue;
+        };
+        let Some(hit) = time_of_impact(
+            shape,
+            start,
+            translation,
+            rotation,
+            &other_collider.shape,
+            other_transform,
+        ) else {
+            continue;
+        };
+        if first_hit
+            .as_ref()
+            .is_some_and(|first_hit| first_hit.time <= hit.time)
+        {
+            continue;
+        }
+        first_hit = Some(hit);
+    }
+
+    let new_transform = match first_hit {
+        Some(hit) => {
+            let mut new_transform = interpolate(start, translation, rotation, hit.time);
+            // a hit that did not converge may still be far from the surface
+            let distance = hit.distance.min(CCD_TOLERANCE);
+            new_transform.translation += (hit.normal * (distance + CCD_CONTACT_DEPTH)).extend(0.);
+            new_transform
+        }
+        None if clipped => interpolate(start, translation, rotation, 1.),
+        None => return,
+    };
+    let Ok((_entity, mut transform, _flat_body, _collider)) = query.get_mut(entity) else {
+        return;
+    };
+    *transform = new_transform;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        flat_body::{BoxParams, CircleParams, on_flat_body_added},
+        flat_world::run_sub_steps,
+    };
+
+    /// Rightmost point of `shape` after firing it at a thin wall.
+    fn fire_at_wall(shape: Shape, angular_velocity: f32, ccd: bool) -> f32 {
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.5),
+            Collider::new(Shape::Box(BoxParams::new(30., 400.))),
+        ));
+        let mut bullet = FlatBody::new(0.1, FlatBodyType::Dynamic, 0.5);
+        bullet.linear_velocity = Vec2::new(3000., 0.);
+        bullet.angular_velocity = angular_velocity;
+        bullet.ccd = ccd;
+        let bullet = world
+            .spawn((
+                Transform::from_xyz(-200., 0., 0.),
+                bullet,
+                Collider::new(shape),
+            ))
+            .id();
+
+        // moves 50 per sub step, more than the wall is thick
+        run_sub_steps(&mut world, 20, 1. / 60.);
+
+        let transform = world.get::<Transform>(bullet).unwrap();
+        let collider = world.get::<Collider>(bullet).unwrap();
+        return collider.shape.get_aabb(transform).max.x;
+    }
+
+    #[test]
+    fn bullet_stops_at_wall() {
+        let circle = Shape::Circle(CircleParams::new(5.));
+        assert!(fire_at_wall(circle.clone(), 0., false) > 15.);
+        let right = fire_at_wall(circle, 0., true);
+        assert!(right < -14., "bullet reached {right}");
+    }
+
+    #[test]
+    fn rotating_thin_box_stops_at_wall() {
+        let thin_box = Shape::Box(BoxParams::new(40., 4.));
+        assert!(fire_at_wall(thin_box.clone(), 30., false) > 15.);
+        let right = fire_at_wall(thin_box, 30., true);
+        assert!(right < -14., "box reached {right}");
+    }
+
+    /// Final height of a ccd ball fired vertically at a one way platform.
+    fn fire_at_platform(y: f32, velocity: f32, drop_through: bool) -> f32 {
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(400., 10.))),
+            OneWayPlatform::default(),
+        ));
+        let mut ball = FlatBody::new(0.1, FlatBodyType::Dynamic, 0.);
+        ball.linear_velocity = Vec2::new(0., velocity);
+        ball.ccd = true;
+        let ball = world
+            .spawn((
+                Transform::from_xyz(0., y, 0.),
+                ball,
+                Collider::new(Shape::Circle(CircleParams::new(5.))),
+            ))
+            .id();
+        if drop_through {
+            world.entity_mut(ball).insert(DropThrough);
+        }
+
+        run_sub_steps(&mut world, 10, 1. / 60.);
+        return world.get::<Transform>(ball).unwrap().translation.y;
+    }
+
+    #[test]
+    fn ccd_respects_one_way_platforms() {
+        let passed = fire_at_platform(-200., 3000., false);
+        assert!(passed > 250., "ball from below ended at {passed}");
+        let landed = fire_at_platform(200., -3000., false);
+        assert!(landed > 9., "ball from above ended at {landed}");
+        let dropped = fire_at_platform(200., -3000., true);
+        assert!(dropped < -250., "dropping ball ended at {dropped}");
+    }
+
+    #[test]
+    fn shallow_bullet_stops_on_thin_box() {
+        // 10 degrees down onto a 4 thick box, 100 along and 17 down per sub
+        // step, so the ball jumps over the box between two sub steps
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(2000., 4.))),
+        ));
+        let mut bullet = FlatBody::new(0.1, FlatBodyType::Dynamic, 0.);
+        bullet.linear_velocity = Vec2::from_angle(-10_f32.to_radians()) * 6000.;
+        bullet.ccd = true;
+        let bullet = world
+            .spawn((
+                Transform::from_xyz(-400., 67., 0.),
+                bullet,
+                Collider::new(Shape::Circle(CircleParams::new(5.))),
+            ))
+            .id();
+
+        run_sub_steps(&mut world, 10, 1. / 60.);
+
+        let y = world.get::<Transform>(bullet).unwrap().translation.y;
+        assert!(y > 5., "bullet ended at {y}");
+    }
+
+    #[test]
+    fn grazing_motion_has_no_impact() {
+        // ball 2 above the tilted top of a wall, closing only 0.5 of the gap
+        // while moving 1000 along it
+        let ball = Shape::Circle(CircleParams::new(5.));
+        let wall = Shape::Box(BoxParams::new(2000., 30.));
+        let wall_transform = Transform::from_rotation(Quat::from_rotation_z(0.3));
+        let along = Vec2::from_angle(0.3);
+        let up = along.perp();
+        let start = Transform::from_translation((along * -500. + up * 22.).extend(0.));
+        let hit = time_of_impact(
+            &ball,
+            &start,
+            along * 1000. - up * 0.5,
+            0.,
+            &wall,
+            &wall_transform,
+        );
+        assert!(hit.is_none());
+
+        // the same ccd ball in the world slides by without being pulled in
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            wall_transform,
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(wall),
+        ));
+        let mut bullet = FlatBody::new(0.1, FlatBodyType::Dynamic, 0.);
+        bullet.linear_velocity = along * 3000. - up;
+        bullet.ccd = true;
+        let bullet = world.spawn((start, bullet, Collider::new(ball))).id();
+
+        run_sub_steps(&mut world, 10, 1. / 60.);
+
+        let position = world
+            .get::<Transform>(bullet)
+            .unwrap()
+            .translation
+            .truncate();
+        assert!(position.dot(along).abs() < 1e-2, "bullet at {position}");
+        assert!(position.dot(up) > 21.5, "bullet at {position}");
+    }
+}
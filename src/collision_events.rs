@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::contacts::ContactCache;
+
+/// Contact between two entities as sent with collision events.
+#[derive(Clone, Debug, Default)]
+pub struct ContactData {
+    /// Points from the first entity to the second one.
+    pub normal: Vec2,
+    /// Contact points of all touching sub-shapes, empty for sensors.
+    pub points: Vec<Vec2>,
+    /// Normal impulse the solver applied in the sub step, summed over all points.
+    pub normal_impulse: f32,
+    pub is_sensor: bool,
+}
+
+impl ContactData {
+    /// Same contact seen from the other entity.
+    fn flipped(&self) -> Self {
+        return ContactData {
+            normal: -self.normal,
+            ..self.clone()
+        };
+    }
+}
+
+/// Two entities started touching. Sent once per pair until they separate.
+#[derive(Message, Clone, Debug)]
+pub struct CollisionStarted(pub Entity, pub Entity, pub ContactData);
+
+/// Two entities stopped touching, carries the last contact they had.
+#[derive(Message, Clone, Debug)]
+pub struct CollisionEnded(pub Entity, pub Entity, pub ContactData);
+
+/// Triggered on both entities when they start touching.
+#[derive(EntityEvent, Clone, Debug)]
+pub struct OnCollisionStart {
+    pub entity: Entity,
+    pub other: Entity,
+    pub data: ContactData,
+}
+
+/// Triggered on both entities when they stop touching.
+#[derive(EntityEvent, Clone, Debug)]
+pub struct OnCollisionEnd {
+    pub entity: Entity,
+    pub other: Entity,
+    pub data: ContactData,
+}
+
+/// Pairs touching after the last world step.
+#[derive(Default)]
+pub struct CollisionTracker {
+    touching: HashMap<(Entity, Entity), ContactData>,
+}
+
+impl CollisionTracker {
+    /// Finds the pairs which started and stopped touching since the last call.
+    pub fn update(
+        &mut self,
+        contacts: &ContactCache,
+        started: &mut Vec<CollisionStarted>,
+        ended: &mut Vec<CollisionEnded>,
+    ) {
+        let mut touching: HashMap<(Entity, Entity), ContactData> = HashMap::new();
+        for contact in contacts.iter() {
+            let points: Vec<_> = contact
+                .contact_points
+                .iter()
+                .filter(|point| point.depth >= 0.)
+                .collect();
+            // speculative and disabled contacts do not touch
+            if !contact.enabled || (!contact.is_sensor && points.is_empty()) {
+                continue;
+            }
+
+            let data = touching
+                .entry((contact.entity_a, contact.entity_b))
+                .or_insert_with(|| ContactData {
+                    normal: contact.collision_normal,
+                    is_sensor: contact.is_sensor,
+                    ..Default::default()
+                });
+            data.points
+                .extend(points.iter().map(|point| point.position));
+            data.normal_impulse += points.iter().map(|point| point.normal_impulse).sum::<f32>();
+        }
+
+        let mut new_pairs: Vec<_> = touching
+            .keys()
+            .filter(|pair| !self.touching.contains_key(pair))
+            .copied()
+            .collect();
+        new_pairs.sort_unstable();
+        for (entity_a, entity_b) in new_pairs {
+            started.push(CollisionStarted(
+                entity_a,
+                entity_b,
+                touching[&(entity_a, entity_b)].clone(),
+            ));
+        }
+
+        let mut old_pairs: Vec<_> = self
+            .touching
+            .keys()
+            .filter(|pair| !touching.contains_key(pair))
+            .copied()
+            .collect();
+        old_pairs.sort_unstable();
+        for (entity_a, entity_b) in old_pairs {
+            let data = self.touching.remove(&(entity_a, entity_b)).unwrap();
+            ended.push(CollisionEnded(entity_a, entity_b, data));
+        }
+
+        self.touching = touching;
+    }
+
+    /// Pairs touching after the last world step with the smaller entity first.
+    pub fn touching_pairs(&self) -> impl Iterator<Item = &(Entity, Entity)> {
+        return self.touching.keys();
+    }
+}
+
+/// Entities the body touches, updated at the end of every `world_step`.
+#[derive(Component, Default, Debug, PartialEq)]
+pub struct CollidingEntities(pub HashSet<Entity>);
+
+impl CollidingEntities {
+    pub fn contains(&self, entity: Entity) -> bool {
+        return self.0.contains(&entity);
+    }
+}
+
+/// Where `world_step` reports the pairs which started and stopped touching.
+#[derive(SystemParam)]
+pub struct CollisionEventWriters<'w, 's> {
+    commands: Commands<'w, 's>,
+    started_writer: MessageWriter<'w, CollisionStarted>,
+    ended_writer: MessageWriter<'w, CollisionEnded>,
+    colliding: Query<'w, 's, (Entity, &'static mut CollidingEntities)>,
+}
+
+impl CollisionEventWriters<'_, '_> {
+    /// Updates `CollidingEntities` from `tracker` and sends the events.
+    pub fn send(
+        &mut self,
+        tracker: &CollisionTracker,
+        started: Vec<CollisionStarted>,
+        ended: Vec<CollisionEnded>,
+        exists: impl Fn(Entity) -> bool,
+    ) {
+        update_colliding_entities(&mut self.colliding, tracker);
+        send_collision_events(
+            &mut self.commands,
+            &mut self.started_writer,
+            &mut self.ended_writer,
+            started,
+            ended,
+            exists,
+        );
+    }
+}
+
+/// Fills every `CollidingEntities` from the pairs touching after the last world step.
+pub fn update_colliding_entities(
+    colliding: &mut Query<'_, '_, (Entity, &mut CollidingEntities)>,
+    tracker: &CollisionTracker,
+) {
+    let mut touching: HashMap<Entity, HashSet<Entity>> = HashMap::new();
+    for (entity_a, entity_b) in tracker.touching_pairs() {
+        touching.entry(*entity_a).or_default().insert(*entity_b);
+        touching.entry(*entity_b).or_default().insert(*entity_a);
+    }
+
+    for (entity, mut colliding_entities) in colliding.iter_mut() {
+        let entities = touching.remove(&entity).unwrap_or_default();
+        // only changed sets trigger change detection
+        colliding_entities.set_if_neq(CollidingEntities(entities));
+    }
+}
+
+/// Sends the collision messages and triggers the entity events.
+fn send_collision_events(
+    commands: &mut Commands,
+    started_writer: &mut MessageWriter<CollisionStarted>,
+    ended_writer: &mut MessageWriter<CollisionEnded>,
+    started: Vec<CollisionStarted>,
+    ended: Vec<CollisionEnded>,
+    exists: impl Fn(Entity) -> bool,
+) {
+    for CollisionStarted(entity_a, entity_b, data) in started.iter() {
+        for (entity, other, data) in [
+            (*entity_a, *entity_b, data.clone()),
+            (*entity_b, *entity_a, data.flipped()),
+        ] {
+            if exists(entity) {
+                commands.trigger(OnCollisionStart {
+                    entity,
+                    other,
+                    data,
+                });
+            }
+        }
+    }
+    for CollisionEnded(entity_a, entity_b, data) in ended.iter() {
+        for (entity, other, data) in [
+            (*entity_a, *entity_b, data.clone()),
+            (*entity_b, *entity_a, data.flipped()),
+        ] {
+            if exists(entity) {
+                commands.trigger(OnCollisionEnd {
+                    entity,
+                    other,
+                    data,
+                });
+            }
+        }
+    }
+
+    started_writer.write_batch(started);
+    ended_writer.write_batch(ended);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::collisions::{ContactPoint, ShapeContact};
+
+    fn entity(index: u32) -> Entity {
+        return Entity::from_raw_u32(index).unwrap();
+    }
+
+    /// Contact between entities 1 and 2 with one point per depth.
+    fn contact(depths: &[f32]) -> ShapeContact {
+        return ShapeContact {
+            entity_a: entity(1),
+            entity_b: entity(2),
+            sub_shape_a: 0,
+            sub_shape_b: 0,
+            collision_normal: Vec2::Y,
+            penetration_depth: 1.,
+            contact_points: depths
+                .iter()
+                .enumerate()
+                .map(|(id, depth)| ContactPoint::new(Vec2::ZERO, *depth, id as u32))
+                .collect(),
+            is_sensor: false,
+            enabled: true,
+            static_friction: 0.,
+            dynamic_friction: 0.,
+            restitution: 0.,
+            tangent_speed: 0.,
+        };
+    }
+
+    /// Number of started and ended pairs after a sub step with `contacts`.
+    fn update(tracker: &mut CollisionTracker, contacts: Vec<ShapeContact>) -> (usize, usize) {
+        let mut cache = ContactCache::default();
+        cache.begin_step();
+        for contact in contacts {
+            cache.insert(contact);
+        }
+        let mut started = Vec::new();
+        let mut ended = Vec::new();
+        tracker.update(&cache, &mut started, &mut ended);
+        return (started.len(), ended.len());
+    }
+
+    #[test]
+    fn pair_starts_and_ends_once() {
+        let mut tracker = CollisionTracker::default();
+        assert_eq!(update(&mut tracker, vec![contact(&[1., -1.])]), (1, 0));
+        assert_eq!(update(&mut tracker, vec![contact(&[2.])]), (0, 0));
+        assert_eq!(
+            tracker.touching_pairs().copied().collect::<Vec<_>>(),
+            [(entity(1), entity(2))]
+        );
+        assert_eq!(update(&mut tracker, Vec::new()), (0, 1));
+        assert_eq!(update(&mut tracker, Vec::new()), (0, 0));
+        assert_eq!(tracker.touching_pairs().count(), 0);
+    }
+
+    #[test]
+    fn speculative_and_disabled_contacts_do_not_touch() {
+        let mut tracker = CollisionTracker::default();
+        for _step in 0..3 {
+            assert_eq!(update(&mut tracker, vec![contact(&[-1., -0.5])]), (0, 0));
+        }
+        let mut disabled = contact(&[1.]);
+        disabled.enabled = false;
+        assert_eq!(update(&mut tracker, vec![disabled]), (0, 0));
+
+        // sensors have no points and still touch
+        let mut sensor = contact(&[]);
+        sensor.is_sensor = true;
+        assert_eq!(update(&mut tracker, vec![sensor]), (1, 0));
+    }
+
+    #[test]
+    fn colliding_entities_follow_touching_pairs() {
+        let mut world = World::new();
+        let a = world.spawn(CollidingEntities::default()).id();
+        let b = world.spawn(CollidingEntities::default()).id();
+        let c = world.spawn(CollidingEntities::default()).id();
+
+        let mut tracker = CollisionTracker::default();
+        let mut touching = contact(&[1.]);
+        touching.entity_a = a;
+        touching.entity_b = b;
+        update(&mut tracker, vec![touching]);
+
+        let fill = |world: &mut World, tracker: &CollisionTracker| {
+            world
+                .run_system_once_with(
+                    |InRef(tracker): InRef<CollisionTracker>,
+                     mut colliding: Query<(Entity, &mut CollidingEntities)>| {
+                        update_colliding_entities(&mut colliding, tracker);
+                    },
+                    tracker,
+                )
+                .unwrap();
+        };
+        fill(&mut world, &tracker);
+        assert!(world.get::<CollidingEntities>(a).unwrap().contains(b));
+        assert!(world.get::<CollidingEntities>(b).unwrap().contains(a));
+        assert!(world.get::<CollidingEntities>(c).unwrap().0.is_empty());
+
+        update(&mut tracker, Vec::new());
+        fill(&mut world, &tracker);
+        for entity in [a, b, c] {
+            assert!(world.get::<CollidingEntities>(entity).unwrap().0.is_empty());
+        }
+    }
+}
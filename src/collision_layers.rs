@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+
+/// Decides which bodies collide with each other. Two bodies collide when
+/// each one is a member of a layer the other one filters for. Bodies without
+/// this component use the default, member of the first layer colliding
+/// with everything.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CollisionLayers {
+    /// Layer bits the body belongs to.
+    pub memberships: u32,
+    /// Layer bits the body collides with.
+    pub filters: u32,
+    /// Bodies sharing a positive group always collide, a negative one never.
+    pub group: i32,
+}
+
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        return CollisionLayers {
+            memberships: 1,
+            filters: u32::MAX,
+            group: 0,
+        };
+    }
+}
+
+impl CollisionLayers {
+    pub fn new(memberships: u32, filters: u32) -> Self {
+        return CollisionLayers {
+            memberships,
+            filters,
+            group: 0,
+        };
+    }
+
+    pub fn with_group(mut self, group: i32) -> Self {
+        self.group = group;
+        return self;
+    }
+
+    pub fn interacts_with(&self, other: &CollisionLayers) -> bool {
+        if self.group != 0 && self.group == other.group {
+            return self.group > 0;
+        }
+        return self.memberships & other.filters != 0 && other.memberships & self.filters != 0;
+    }
+}
+
+/// Checks the layers of two entities, missing components count as default.
+pub fn layers_interact(
+    layers: &Query<'_, '_, &CollisionLayers>,
+    entity_a: Entity,
+    entity_b: Entity,
+) -> bool {
+    let layers_a = layers.get(entity_a).copied().unwrap_or_default();
+    let layers_b = layers.get(entity_b).copied().unwrap_or_default();
+    return layers_a.interacts_with(&layers_b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_and_groups() {
+        let player = CollisionLayers::new(0b01, 0b10);
+        let enemy = CollisionLayers::new(0b10, 0b01);
+        let ghost = CollisionLayers::new(0b100, 0b100);
+        assert!(player.interacts_with(&enemy));
+        assert!(enemy.interacts_with(&player));
+        assert!(!player.interacts_with(&ghost));
+        // both sides have to filter for each other
+        let one_sided = CollisionLayers::new(0b01, 0b100);
+        assert!(!one_sided.interacts_with(&enemy));
+        assert!(!enemy.interacts_with(&one_sided));
+
+        // negative group never collides, even with matching layers
+        let part_a = CollisionLayers::default().with_group(-1);
+        let part_b = CollisionLayers::default().with_group(-1);
+        assert!(!part_a.interacts_with(&part_b));
+        // positive group always collides, even with disjoint layers
+        let linked_a = player.with_group(3);
+        let linked_b = ghost.with_group(3);
+        assert!(linked_a.interacts_with(&linked_b));
+        // different groups fall back to the layers
+        assert!(player.with_group(3).interacts_with(&enemy.with_group(-1)));
+        assert!(!player.with_group(3).interacts_with(&ghost.with_group(4)));
+    }
+}
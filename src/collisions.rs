@@ -1,24 +1,153 @@
 use core::f32;
+use std::borrow::Cow;
 
 use crate::flat_aabb::FlatAABB;
-use crate::helpers::{get_global_vertices, nearly_equal, nearly_equal_vec};
+use crate::gjk::{ConvexCore, convex_core};
+use crate::helpers::{get_global_vertices, nearly_equal_vec};
 use crate::{
-    flat_body::{BoxParams, CircleParams, FlatBody, FlatBodyType},
+    flat_body::{
+        BoxParams, CapsuleParams, ChainParams, CircleParams, CompoundParams, PolygonParams,
+        SegmentParams,
+    },
     helpers::{to_vec2, to_vec3},
 };
 use bevy::prelude::*;
 use bevy::{ecs::component::Component, math::Vec2};
 
-#[derive(Component, Default)]
+#[derive(Component)]
 pub struct Collider {
     aabb: FlatAABB,
+    /// Cached `aabb` is outdated and is computed again on the next `get_aabb`.
     update_aabb: bool,
     pub shape: Shape,
+    /// Sensors only report overlaps, bodies pass through them.
+    pub sensor: bool,
 }
 
+impl Default for Collider {
+    fn default() -> Self {
+        Collider {
+            aabb: FlatAABB::default(),
+            update_aabb: true,
+            shape: Shape::default(),
+            sensor: false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Shape {
     Box(BoxParams),
     Circle(CircleParams),
+    Polygon(PolygonParams),
+    Capsule(CapsuleParams),
+    Segment(SegmentParams),
+    Chain(ChainParams),
+    Compound(CompoundParams),
+}
+
+impl Shape {
+    /// Local vertices of convex polygonal shapes, `None` for round shapes and chains.
+    pub fn verticies(&self) -> Option<&[Vec2]> {
+        match self {
+            Shape::Box(box_params) => Some(&box_params.verticies),
+            Shape::Polygon(polygon_params) => Some(&polygon_params.verticies),
+            Shape::Segment(segment_params) => Some(&segment_params.verticies),
+            Shape::Circle(_) | Shape::Capsule(_) | Shape::Chain(_) | Shape::Compound(_) => None,
+        }
+    }
+
+    /// Convex pieces of the shape with their index and local transform.
+    pub fn sub_shapes(&self) -> Vec<(usize, Transform, Cow<'_, Shape>)> {
+        match self {
+            Shape::Compound(compound_params) => {
+                let mut sub_shapes = Vec::new();
+                for child in compound_params.children.iter() {
+                    let child_transform = child.local_transform();
+                    for (_index, local, shape) in child.shape.sub_shapes() {
+                        sub_shapes.push((
+                            sub_shapes.len(),
+                            child_transform.mul_transform(local),
+                            shape,
+                        ));
+                    }
+                }
+                sub_shapes
+            }
+            Shape::Chain(chain_params) => (0..chain_params.segment_count())
+                .map(|i| {
+                    (
+                        i,
+                        Transform::IDENTITY,
+                        Cow::Owned(Shape::Segment(chain_params.segment(i))),
+                    )
+                })
+                .collect(),
+            _ => vec![(0, Transform::IDENTITY, Cow::Borrowed(self))],
+        }
+    }
+
+    pub fn get_aabb(&self, transform: &Transform) -> FlatAABB {
+        let (min_x, min_y, max_x, max_y) = match self {
+            Shape::Compound(compound_params) => {
+                let mut bounds = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+                for child in compound_params.children.iter() {
+                    let child_transform = transform.mul_transform(child.local_transform());
+                    let aabb = child.shape.get_aabb(&child_transform);
+                    bounds.0 = bounds.0.min(aabb.min.x);
+                    bounds.1 = bounds.1.min(aabb.min.y);
+                    bounds.2 = bounds.2.max(aabb.max.x);
+                    bounds.3 = bounds.3.max(aabb.max.y);
+                }
+                bounds
+            }
+            Shape::Box(_) | Shape::Polygon(_) | Shape::Segment(_) => {
+                vertices_bounds(transform, self.verticies().unwrap())
+            }
+            Shape::Chain(chain_params) => vertices_bounds(transform, &chain_params.verticies),
+            Shape::Circle(circle_params) => (
+                transform.translation.x - circle_params.radius,
+                transform.translation.y - circle_params.radius,
+                transform.translation.x + circle_params.radius,
+                transform.translation.y + circle_params.radius,
+            ),
+            Shape::Capsule(capsule_params) => {
+                let (a, b) = capsule_params.segment(transform);
+                (
+                    a.x.min(b.x) - capsule_params.radius,
+                    a.y.min(b.y) - capsule_params.radius,
+                    a.x.max(b.x) + capsule_params.radius,
+                    a.y.max(b.y) + capsule_params.radius,
+                )
+            }
+        };
+        return FlatAABB::new(min_x, min_y, max_x, max_y);
+    }
+}
+
+/// returns min_x, min_y, max_x, max_y of transformed vertices
+fn vertices_bounds(transform: &Transform, verticies: &[Vec2]) -> (f32, f32, f32, f32) {
+    let mut min_x = f32::MAX;
+    let mut min_y = f32::MAX;
+    let mut max_x = f32::MIN;
+    let mut max_y = f32::MIN;
+
+    let vertices = get_global_vertices(transform, verticies);
+    for v in vertices {
+        if v.x < min_x {
+            min_x = v.x;
+        }
+        if v.x > max_x {
+            max_x = v.x;
+        }
+        if v.y < min_y {
+            min_y = v.y;
+        }
+        if v.y > max_y {
+            max_y = v.y;
+        }
+    }
+    return (min_x, min_y, max_x, max_y);
 }
 
 impl Default for Shape {
@@ -34,7 +163,15 @@ impl Default for Shape {
 impl Collider {
     pub fn new(shape: Shape) -> Self {
         Collider {
-            shape: shape,
+            shape,
+            ..Default::default()
+        }
+    }
+
+    pub fn sensor(shape: Shape) -> Self {
+        Collider {
+            shape,
+            sensor: true,
             ..Default::default()
         }
     }
@@ -43,42 +180,16 @@ impl Collider {
         self.update_aabb = true;
     }
 
+    pub fn needs_aabb_update(&self) -> bool {
+        self.update_aabb
+    }
+
     pub fn get_aabb(&mut self, transform: &Transform) -> &FlatAABB {
-        // if !self.update_aabb {
-        //     return &self.aabb;
-        // }
-
-        let mut min_x = f32::MAX;
-        let mut min_y = f32::MAX;
-        let mut max_x = f32::MIN;
-        let mut max_y = f32::MIN;
-
-        match &self.shape {
-            Shape::Box(box_params) => {
-                let vertices = get_global_vertices(transform, &box_params.verticies);
-                for v in vertices {
-                    if v.x < min_x {
-                        min_x = v.x;
-                    }
-                    if v.x > max_x {
-                        max_x = v.x;
-                    }
-                    if v.y < min_y {
-                        min_y = v.y;
-                    }
-                    if v.y > max_y {
-                        max_y = v.y;
-                    }
-                }
-            }
-            Shape::Circle(circle_params) => {
-                min_x = transform.translation.x - circle_params.radius;
-                min_y = transform.translation.y - circle_params.radius;
-                max_x = transform.translation.x + circle_params.radius;
-                max_y = transform.translation.y + circle_params.radius;
-            }
+        if !self.update_aabb {
+            return &self.aabb;
         }
-        self.aabb = FlatAABB::new(min_x, min_y, max_x, max_y);
+
+        self.aabb = self.shape.get_aabb(transform);
         self.update_aabb = false;
         return &self.aabb;
     }
@@ -89,7 +200,28 @@ pub struct CollisionDetails {
     pub collision_normal: Vec2,
 }
 
-fn find_closes_point_on_polygon(circle_center: &Vec2, vertices: &[Vec2; 4]) -> Option<usize> {
+/// Contact found by the narrow phase.
+pub struct ShapeContact {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    pub sub_shape_a: usize,
+    pub sub_shape_b: usize,
+    pub collision_normal: Vec2,
+    pub penetration_depth: f32,
+    pub contact_points: ContactPoints,
+    /// One of the colliders is a sensor, the solver skips this contact.
+    pub is_sensor: bool,
+    /// The solver skips disabled contacts, contact hooks can turn them off.
+    pub enabled: bool,
+    /// Friction and restitution of the pair, combined from both bodies.
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    pub restitution: f32,
+    /// Tangent speed of B relative to A that friction drives towards.
+    pub tangent_speed: f32,
+}
+
+fn find_closes_point_on_polygon(circle_center: &Vec2, vertices: &[Vec2]) -> Option<usize> {
     let mut result = None;
     let mut min_distance = f32::MAX;
 
@@ -125,7 +257,7 @@ fn project_circle(center: &Vec2, radius: f32, axis: &Vec2) -> (f32, f32) {
 pub fn intersect_circle_polygon(
     circle_center: &Vec2,
     circle_radius: f32,
-    vertices: &[Vec2; 4],
+    vertices: &[Vec2],
     polygon_center: &Vec2,
 ) -> Option<CollisionDetails> {
     let mut normal = Vec2::ZERO;
@@ -195,7 +327,8 @@ pub fn intersect_circle_circle(
         return None;
     }
 
-    let normal = (pos_b - pos_a).normalize();
+    // circles on the same center can be pushed apart in any direction
+    let normal = (pos_b - pos_a).normalize_or(Vec2::Y);
 
     let depth = radius_sum - distance;
 
@@ -206,9 +339,9 @@ pub fn intersect_circle_circle(
 }
 
 pub fn intersects_polygons(
-    vertices_a: &[Vec2; 4],
+    vertices_a: &[Vec2],
     center_a: &Vec2,
-    vertices_b: &[Vec2; 4],
+    vertices_b: &[Vec2],
     center_b: &Vec2,
 ) -> Option<CollisionDetails> {
     let mut normal = Vec2::ZERO;
@@ -264,7 +397,7 @@ pub fn intersects_polygons(
     });
 }
 
-fn project_vertices(vertices: &[Vec2; 4], axis: &Vec2) -> (f32, f32) {
+fn project_vertices(vertices: &[Vec2], axis: &Vec2) -> (f32, f32) {
     let mut max = f32::MIN;
     let mut min = f32::MAX;
 
@@ -280,32 +413,124 @@ fn project_vertices(vertices: &[Vec2; 4], axis: &Vec2) -> (f32, f32) {
     return (min, max);
 }
 
-pub fn separate_bodies(
-    transform_a: &mut Transform,
-    transform_b: &mut Transform,
-    flat_body_a: &FlatBody,
-    flat_body_b: &FlatBody,
-    collision_info: &crate::collisions::CollisionDetails,
-) {
-    if let FlatBodyType::Static = flat_body_a.body_type {
-        transform_b.translation +=
-            to_vec3(&(collision_info.collision_normal * collision_info.penetration_depth));
-    } else if let FlatBodyType::Static = flat_body_b.body_type {
-        transform_a.translation +=
-            to_vec3(&(-collision_info.collision_normal * collision_info.penetration_depth));
+/// Projection of a shape placed at `transform` onto `axis`.
+pub fn project_shape(shape: &Shape, transform: &Transform, axis: &Vec2) -> (f32, f32) {
+    match shape {
+        Shape::Circle(circle_params) => {
+            project_circle(&to_vec2(&transform.translation), circle_params.radius, axis)
+        }
+        Shape::Capsule(capsule_params) => {
+            let (a, b) = capsule_params.segment(transform);
+            let (min, max) = project_vertices(&[a, b], axis);
+            let radius = capsule_params.radius * axis.length();
+            (min - radius, max + radius)
+        }
+        Shape::Chain(chain_params) => project_vertices(
+            &get_global_vertices(transform, &chain_params.verticies),
+            axis,
+        ),
+        Shape::Box(_) | Shape::Polygon(_) | Shape::Segment(_) => project_vertices(
+            &get_global_vertices(transform, shape.verticies().unwrap()),
+            axis,
+        ),
+        Shape::Compound(compound_params) => {
+            let mut projection = (f32::MAX, f32::MIN);
+            for child in compound_params.children.iter() {
+                let child_transform = transform.mul_transform(child.local_transform());
+                let (min, max) = project_shape(&child.shape, &child_transform, axis);
+                projection.0 = projection.0.min(min);
+                projection.1 = projection.1.max(max);
+            }
+            projection
+        }
+    }
+}
+
+/// Corrects collision against a segment which is part of a chain.
+pub fn apply_ghost_vertices(
+    segment_params: &SegmentParams,
+    segment_transform: &Transform,
+    other_shape: &Shape,
+    other_transform: &Transform,
+    collision: CollisionDetails,
+    speculative_distance: f32,
+) -> Option<CollisionDetails> {
+    let [a, b] = get_global_vertices(segment_transform, &segment_params.verticies)[..] else {
+        return Some(collision);
+    };
+    let edge = b - a;
+    if edge.length_squared() == 0. {
+        return Some(collision);
+    }
+
+    let normal = collision.collision_normal;
+    let side = if Vec2::new(-edge.y, edge.x).dot(normal) >= 0. {
+        1.
+    } else {
+        -1.
+    };
+    let edge_normal = Vec2::new(-edge.y, edge.x).normalize() * side;
+
+    if nearly_equal_vec(&normal, &edge_normal) {
+        return Some(collision);
+    }
+
+    // decide which end of the segment is being touched
+    let other_center = to_vec2(&other_transform.translation);
+    let t = (other_center - a).dot(edge) / edge.length_squared();
+    let (vertex, ghost, neighbour_edge) = if t <= 0.5 {
+        let ghost = segment_params
+            .ghost_start
+            .map(|g| to_vec2(&segment_transform.transform_point(to_vec3(&g))));
+        (a, ghost, ghost.map(|g| a - g))
     } else {
-        transform_a.translation +=
-            to_vec3(&(-collision_info.collision_normal * collision_info.penetration_depth / 2.));
+        let ghost = segment_params
+            .ghost_end
+            .map(|g| to_vec2(&segment_transform.transform_point(to_vec3(&g))));
+        (b, ghost, ghost.map(|g| g - b))
+    };
+
+    let (Some(ghost), Some(neighbour_edge)) = (ghost, neighbour_edge) else {
+        // end of the chain is a real corner
+        return Some(collision);
+    };
 
-        transform_b.translation +=
-            to_vec3(&(collision_info.collision_normal * collision_info.penetration_depth / 2.));
+    // a flat or concave joint has no corner to hit, use the segment normal,
+    // at a convex one keep normals between both segment normals
+    let convex = (ghost - vertex).dot(edge_normal) < 0.;
+    if convex && neighbour_edge.length_squared() > 0. {
+        let neighbour_normal = Vec2::new(-neighbour_edge.y, neighbour_edge.x).normalize() * side;
+        let fan = edge_normal.perp_dot(neighbour_normal);
+        let from_edge = edge_normal.perp_dot(normal);
+        let to_neighbour = normal.perp_dot(neighbour_normal);
+
+        if from_edge * fan >= 0. && to_neighbour * fan >= 0. {
+            return Some(collision);
+        }
+        if from_edge * fan >= 0. {
+            // normal belongs to the neighbouring segment
+            return None;
+        }
+    }
+
+    // negative depth up to `speculative_distance` keeps a speculative contact
+    let (min, _max) = project_shape(other_shape, other_transform, &edge_normal);
+    let depth = a.dot(edge_normal) - min;
+    if depth <= -speculative_distance {
+        return None;
     }
+
+    return Some(CollisionDetails {
+        penetration_depth: depth,
+        collision_normal: edge_normal,
+    });
 }
 
 /// returns contact point
 pub fn find_contanct_point(center_a: &Vec2, radius_a: f32, center_b: &Vec2) -> Vec2 {
     let ab = center_b - center_a;
-    let direction = ab.normalize();
+    // same fallback as `intersect_circle_circle` for centers on top of each other
+    let direction = ab.normalize_or(Vec2::Y);
     let center_point = center_a + direction * radius_a;
     return center_point;
 }
@@ -333,10 +558,7 @@ fn point_segment_distance(p: &Vec2, a: &Vec2, b: &Vec2) -> (f32, Vec2) {
     return (distance_squared, contact_point);
 }
 
-fn find_contact_point_polygon_circle(
-    circle_center: &Vec2,
-    polygon_vertices: &[Vec2; 4],
-) -> ContactPoints {
+fn find_contact_point_polygon_circle(circle_center: &Vec2, polygon_vertices: &[Vec2]) -> Vec2 {
     let mut cp = Vec2::ZERO;
     let mut min_distance_squared = f32::MAX;
 
@@ -351,99 +573,287 @@ fn find_contact_point_polygon_circle(
             cp = contact.clone();
         }
     }
-    return vec![cp];
+    return cp;
 }
 
-fn find_contact_points_polygon_polygon(
-    vertices_a: &[Vec2; 4],
-    vertices_b: &[Vec2; 4],
-) -> ContactPoints {
-    let mut contact1 = Vec2::ZERO;
-    let mut contact2 = Vec2::ZERO;
-    let mut min_dist_sq = f32::MAX;
-    let mut contact_count = 0;
+/// Pair of shape features that produced a contact point.
+pub type ContactId = u32;
+
+/// Contact point, speculative ones have a negative depth.
+#[derive(Clone, Copy)]
+pub struct ContactPoint {
+    pub position: Vec2,
+    pub depth: f32,
+    pub id: ContactId,
+    /// Point was matched by id to a point of the previous step.
+    pub persisted: bool,
+    /// Accumulated impulses from the solver, reused to warm start the next step.
+    pub normal_impulse: f32,
+    pub tangent_impulse: f32,
+}
 
-    for i in 0..vertices_a.len() {
-        let p = vertices_a[i];
-        for j in 0..vertices_b.len() {
-            let va = vertices_b[j];
-            let vb = vertices_b[(j + 1) % vertices_b.len()];
+impl ContactPoint {
+    pub fn new(position: Vec2, depth: f32, id: ContactId) -> Self {
+        return ContactPoint {
+            position,
+            depth,
+            id,
+            persisted: false,
+            normal_impulse: 0.,
+            tangent_impulse: 0.,
+        };
+    }
+}
 
-            let (distance_squared, contact_point) = point_segment_distance(&p, &va, &vb);
+/// Incident vertex was cut by a side plane of the reference edge.
+const CLIPPED_FEATURE: u32 = 0b100;
+/// Reference edge belongs to shape B.
+const FLIPPED_FEATURE: u32 = 1 << 24;
+/// Contact between the two deepest vertices without clipping.
+const VERTEX_FEATURE: u32 = 1 << 25;
+
+fn contact_id(reference_edge: usize, incident_edge: usize, feature: u32, flip: bool) -> ContactId {
+    let mut id = (reference_edge as u32 & 0xff)
+        | (incident_edge as u32 & 0xff) << 8
+        | (feature & 0xff) << 16;
+    if flip {
+        id |= FLIPPED_FEATURE;
+    }
+    return id;
+}
 
-            if nearly_equal(distance_squared, min_dist_sq) {
-                if !nearly_equal_vec(&contact_point, &contact1) {
-                    contact2 = contact_point.clone();
-                    contact_count = 2;
-                }
-            } else if distance_squared < min_dist_sq {
-                min_dist_sq = distance_squared;
-                contact1 = contact_point;
-                contact_count = 1;
-            }
+pub type ContactPoints = Vec<ContactPoint>;
+
+/// Edges more aligned with the normal than this count as face contacts.
+const FACE_CONTACT_TOLERANCE: f32 = 0.005;
+/// Reference edge prefers shape A unless B is better by this much.
+const REFERENCE_EDGE_TOLERANCE: f32 = 0.001;
+/// Clipped points separated by less than this still count as touching.
+const CONTACT_SEPARATION_TOLERANCE: f32 = 0.1;
+
+struct ClipEdge {
+    v1: Vec2,
+    v2: Vec2,
+    normal: Vec2,
+}
+
+/// Edges of the core with their outward normals.
+fn core_edges(core: &ConvexCore) -> Vec<ClipEdge> {
+    let points = &core.points;
+    let mut signed_area = 0.;
+    for i in 0..points.len() {
+        signed_area += points[i].perp_dot(points[(i + 1) % points.len()]);
+    }
+
+    let mut edges = Vec::with_capacity(points.len());
+    for i in 0..points.len() {
+        let v1 = points[i];
+        let v2 = points[(i + 1) % points.len()];
+        let edge = (v2 - v1).normalize_or_zero();
+        let normal = if signed_area >= 0. {
+            Vec2::new(edge.y, -edge.x)
+        } else {
+            Vec2::new(-edge.y, edge.x)
+        };
+        edges.push(ClipEdge { v1, v2, normal });
+    }
+    return edges;
+}
+
+/// Edge whose normal is most aligned with `direction` and that alignment.
+fn best_edge(edges: &[ClipEdge], direction: Vec2) -> (usize, f32) {
+    let mut best = 0;
+    let mut best_dot = f32::MIN;
+    for (i, edge) in edges.iter().enumerate() {
+        let dot = edge.normal.dot(direction);
+        if dot > best_dot {
+            best_dot = dot;
+            best = i;
         }
     }
+    return (best, best_dot);
+}
 
-    for i in 0..vertices_b.len() {
-        let p = vertices_b[i];
-        for j in 0..vertices_a.len() {
-            let va = vertices_a[j];
-            let vb = vertices_a[(j + 1) % vertices_b.len()];
+/// Keeps the part of segment `v1`-`v2` where `dot(normal, p) <= offset`.
+fn clip_segment(
+    v1: (Vec2, u32),
+    v2: (Vec2, u32),
+    normal: Vec2,
+    offset: f32,
+    clip_feature: u32,
+) -> Option<((Vec2, u32), (Vec2, u32))> {
+    let distance1 = normal.dot(v1.0) - offset;
+    let distance2 = normal.dot(v2.0) - offset;
+
+    if distance1 > 0. && distance2 > 0. {
+        return None;
+    }
+    if distance1 <= 0. && distance2 <= 0. {
+        return Some((v1, v2));
+    }
 
-            let (distance_squared, contact_point) = point_segment_distance(&p, &va, &vb);
+    let intersection = v1.0 + (v2.0 - v1.0) * (distance1 / (distance1 - distance2));
+    if distance1 > 0. {
+        return Some(((intersection, clip_feature), v2));
+    }
+    return Some((v1, (intersection, clip_feature)));
+}
 
-            if nearly_equal(distance_squared, min_dist_sq) {
-                if !nearly_equal_vec(&contact_point, &contact1) {
-                    contact2 = contact_point.clone();
-                    contact_count = 2;
-                }
-            } else if distance_squared < min_dist_sq {
-                min_dist_sq = distance_squared;
-                contact1 = contact_point;
-                contact_count = 1;
-            }
-        }
+/// Single contact between the deepest features, used for corner contacts.
+fn deepest_point_contact(
+    core_a: &ConvexCore,
+    core_b: &ConvexCore,
+    collision: &CollisionDetails,
+) -> ContactPoints {
+    let normal = collision.collision_normal;
+    let index_a = core_a.support(normal);
+    let index_b = core_b.support(-normal);
+    let point_a = core_a.points[index_a] + normal * core_a.radius;
+    let point_b = core_b.points[index_b] - normal * core_b.radius;
+    return vec![ContactPoint::new(
+        (point_a + point_b) / 2.,
+        collision.penetration_depth,
+        contact_id(index_a, index_b, 0, false) | VERTEX_FEATURE,
+    )];
+}
+
+/// Contact manifold of two convex cores by reference/incident edge clipping.
+fn clip_contact_points(
+    core_a: &ConvexCore,
+    core_b: &ConvexCore,
+    collision: &CollisionDetails,
+) -> ContactPoints {
+    let normal = collision.collision_normal;
+    let edges_a = core_edges(core_a);
+    let edges_b = core_edges(core_b);
+
+    let (edge_a, dot_a) = best_edge(&edges_a, normal);
+    let (edge_b, dot_b) = best_edge(&edges_b, -normal);
+
+    if dot_a.max(dot_b) < 1. - FACE_CONTACT_TOLERANCE {
+        // rounded or corner to corner contact, there is no face to clip against
+        return deepest_point_contact(core_a, core_b, collision);
     }
 
-    if contact_count == 2 {
-        return vec![contact1, contact2];
+    let flip = dot_b > dot_a + REFERENCE_EDGE_TOLERANCE;
+    let (reference_index, reference_edges, reference_radius, incident_edges, incident_radius) =
+        if flip {
+            (edge_b, &edges_b, core_b.radius, &edges_a, core_a.radius)
+        } else {
+            (edge_a, &edges_a, core_a.radius, &edges_b, core_b.radius)
+        };
+    let reference = &reference_edges[reference_index];
+    let reference_normal = reference.normal;
+    let (incident_index, _) = best_edge(incident_edges, -reference_normal);
+    let incident = &incident_edges[incident_index];
+
+    let tangent = (reference.v2 - reference.v1).normalize_or_zero();
+    let clipped = clip_segment(
+        (incident.v1, 0),
+        (incident.v2, 1),
+        -tangent,
+        -tangent.dot(reference.v1),
+        CLIPPED_FEATURE,
+    )
+    .and_then(|(v1, v2)| {
+        clip_segment(
+            v1,
+            v2,
+            tangent,
+            tangent.dot(reference.v2),
+            CLIPPED_FEATURE | 1,
+        )
+    });
+
+    let Some((v1, v2)) = clipped else {
+        return deepest_point_contact(core_a, core_b, collision);
     };
 
-    return vec![contact1];
-}
+    // speculative contacts keep every point within their gap
+    let speculative = collision.penetration_depth < 0.;
+    let max_separation = CONTACT_SEPARATION_TOLERANCE.max(-collision.penetration_depth);
+
+    let mut contact_points = Vec::with_capacity(2);
+    for (point, feature) in [v1, v2] {
+        let distance = (point - reference.v1).dot(reference_normal);
+        let separation = distance - reference_radius - incident_radius;
+        if separation > max_separation {
+            continue;
+        }
+
+        // midway between the two surfaces
+        let reference_surface = point - reference_normal * (distance - reference_radius);
+        let incident_surface = point - reference_normal * incident_radius;
+        let position = (reference_surface + incident_surface) / 2.;
 
-pub type ContactPoints = Vec<Vec2>;
+        if contact_points
+            .iter()
+            .any(|contact: &ContactPoint| nearly_equal_vec(&contact.position, &position))
+        {
+            continue;
+        }
+        contact_points.push(ContactPoint::new(
+            position,
+            if speculative {
+                -separation
+            } else {
+                (-separation).max(0.)
+            },
+            contact_id(reference_index, incident_index, feature, flip),
+        ));
+    }
+
+    if contact_points.is_empty() {
+        return deepest_point_contact(core_a, core_b, collision);
+    }
+    return contact_points;
+}
 
 pub fn find_contanct_points(
     trans_a: &Transform,
-    collider_a: &Collider,
+    shape_a: &Shape,
     trans_b: &Transform,
-    collider_b: &Collider,
+    shape_b: &Shape,
+    collision: &CollisionDetails,
 ) -> ContactPoints {
-    match (&collider_a.shape, &collider_b.shape) {
-        (Shape::Box(box_params_a), Shape::Box(box_params_b)) => {
-            let vertices_a = get_global_vertices(&trans_a, &box_params_a.verticies);
-            let vertices_b = get_global_vertices(&trans_b, &box_params_b.verticies);
-
-            return find_contact_points_polygon_polygon(&vertices_a, &vertices_b);
+    let depth = collision.penetration_depth;
+    let position = match (shape_a, shape_b) {
+        (Shape::Circle(circle_params_a), Shape::Circle(_circle_params_b)) => find_contanct_point(
+            &to_vec2(&trans_a.translation),
+            circle_params_a.radius,
+            &to_vec2(&trans_b.translation),
+        ),
+        (Shape::Capsule(capsule_params_a), Shape::Circle(_circle_params_b)) => {
+            let (a1, a2) = capsule_params_a.segment(trans_a);
+            let center_b = to_vec2(&trans_b.translation);
+            let (_distance_squared, closest) = point_segment_distance(&center_b, &a1, &a2);
+            find_contanct_point(&closest, capsule_params_a.radius, &center_b)
         }
-        (Shape::Box(box_params_a), Shape::Circle(_circle_params_b)) => {
-            let vertices_a = get_global_vertices(&trans_a, &box_params_a.verticies);
-            return find_contact_point_polygon_circle(&to_vec2(&trans_b.translation), &vertices_a);
+        (Shape::Circle(circle_params_a), Shape::Capsule(capsule_params_b)) => {
+            let (b1, b2) = capsule_params_b.segment(trans_b);
+            let center_a = to_vec2(&trans_a.translation);
+            let (_distance_squared, closest) = point_segment_distance(&center_a, &b1, &b2);
+            find_contanct_point(&center_a, circle_params_a.radius, &closest)
         }
-        (Shape::Circle(_circle_params_a), Shape::Box(box_params_b)) => {
-            let vertices_b = get_global_vertices(&trans_b, &box_params_b.verticies);
-            return find_contact_point_polygon_circle(&to_vec2(&trans_a.translation), &vertices_b);
+        (shape_a, Shape::Circle(_circle_params_b)) if shape_a.verticies().is_some() => {
+            let vertices_a = get_global_vertices(&trans_a, shape_a.verticies().unwrap());
+            find_contact_point_polygon_circle(&to_vec2(&trans_b.translation), &vertices_a)
         }
-        (Shape::Circle(circle_params_a), Shape::Circle(_circle_params_b)) => {
-            let contact_point = find_contanct_point(
-                &to_vec2(&trans_a.translation),
-                circle_params_a.radius,
-                &to_vec2(&trans_b.translation),
-            );
-            vec![contact_point]
+        (Shape::Circle(_circle_params_a), shape_b) if shape_b.verticies().is_some() => {
+            let vertices_b = get_global_vertices(&trans_b, shape_b.verticies().unwrap());
+            find_contact_point_polygon_circle(&to_vec2(&trans_a.translation), &vertices_b)
         }
-    }
+        (shape_a, shape_b) => {
+            let (Some(core_a), Some(core_b)) =
+                (convex_core(shape_a, trans_a), convex_core(shape_b, trans_b))
+            else {
+                return Vec::new();
+            };
+            return clip_contact_points(&core_a, &core_b, collision);
+        }
+    };
+
+    return vec![ContactPoint::new(position, depth, 0)];
 }
 
 pub fn intersect_aabbs(a: &FlatAABB, b: &FlatAABB) -> bool {
@@ -453,3 +863,163 @@ pub fn intersect_aabbs(a: &FlatAABB, b: &FlatAABB) -> bool {
 
     return true;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::{flat_body::CompoundChild, helpers::vertices_center};
+
+    fn box_contact_points(transform_b: Transform) -> ContactPoints {
+        let box_shape = Shape::Box(BoxParams::new(100., 100.));
+        let core_a = convex_core(&box_shape, &Transform::IDENTITY).unwrap();
+        let core_b = convex_core(&box_shape, &transform_b).unwrap();
+        let vertices_a = get_global_vertices(&Transform::IDENTITY, box_shape.verticies().unwrap());
+        let vertices_b = get_global_vertices(&transform_b, box_shape.verticies().unwrap());
+        let collision = intersects_polygons(
+            &vertices_a,
+            &Vec2::ZERO,
+            &vertices_b,
+            &to_vec2(&transform_b.translation),
+        )
+        .expect("boxes overlap");
+        return clip_contact_points(&core_a, &core_b, &collision);
+    }
+
+    #[test]
+    fn resting_boxes_touch_along_the_edge() {
+        // upper box sunk 5 into the lower one, shifted sideways
+        let contact_points = box_contact_points(Transform::from_xyz(10., 95., 0.));
+        assert_eq!(contact_points.len(), 2);
+        let mut xs: Vec<f32> = contact_points
+            .iter()
+            .map(|point| point.position.x)
+            .collect();
+        xs.sort_by(f32::total_cmp);
+        assert!((xs[0] + 40.).abs() < 1e-3, "points {xs:?}");
+        assert!((xs[1] - 50.).abs() < 1e-3, "points {xs:?}");
+        for point in contact_points.iter() {
+            assert!((point.depth - 5.).abs() < 1e-3, "depth {}", point.depth);
+            assert!((point.position.y - 47.5).abs() < 1e-3);
+        }
+        assert_ne!(contact_points[0].id, contact_points[1].id);
+    }
+
+    #[test]
+    fn tilted_box_has_depth_per_point() {
+        // left corner of the upper box is about 6.44 deep, the edge leaves the
+        // lower box about 1.56 deep at its right side
+        let contact_points = box_contact_points(
+            Transform::from_xyz(0., 96., 0.).with_rotation(Quat::from_rotation_z(0.05)),
+        );
+        assert_eq!(contact_points.len(), 2);
+        let mut depths: Vec<f32> = contact_points.iter().map(|point| point.depth).collect();
+        depths.sort_by(f32::total_cmp);
+        assert!((depths[0] - 1.56).abs() < 1e-2, "depths {depths:?}");
+        assert!((depths[1] - 6.44).abs() < 1e-2, "depths {depths:?}");
+    }
+
+    fn triangle() -> Shape {
+        // flat 60 wide bottom edge, centroid at the origin
+        return Shape::Polygon(
+            PolygonParams::new(vec![
+                Vec2::new(-30., -20.),
+                Vec2::new(30., -20.),
+                Vec2::new(0., 40.),
+            ])
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn rotated_polygon_aabb() {
+        let transform =
+            Transform::from_xyz(100., 50., 0.).with_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        let aabb = triangle().get_aabb(&transform);
+        // turned a quarter left, the bottom edge faces right
+        assert!(
+            aabb.min.distance(Vec2::new(60., 20.)) < 1e-3,
+            "min {}",
+            aabb.min
+        );
+        assert!(
+            aabb.max.distance(Vec2::new(120., 80.)) < 1e-3,
+            "max {}",
+            aabb.max
+        );
+    }
+
+    #[test]
+    fn triangle_rests_on_box_edge() {
+        let box_shape = Shape::Box(BoxParams::new(100., 100.));
+        let triangle = triangle();
+        // bottom edge sunk 2 into the top of the box
+        let triangle_transform = Transform::from_xyz(0., 68., 0.);
+        let vertices_a = get_global_vertices(&Transform::IDENTITY, box_shape.verticies().unwrap());
+        let vertices_b = get_global_vertices(&triangle_transform, triangle.verticies().unwrap());
+        let collision = intersects_polygons(
+            &vertices_a,
+            &vertices_center(&vertices_a),
+            &vertices_b,
+            &vertices_center(&vertices_b),
+        )
+        .expect("triangle overlaps the box");
+        assert!(collision.collision_normal.distance(Vec2::Y) < 1e-3);
+        assert!((collision.penetration_depth - 2.).abs() < 1e-3);
+
+        let contact_points = find_contanct_points(
+            &Transform::IDENTITY,
+            &box_shape,
+            &triangle_transform,
+            &triangle,
+            &collision,
+        );
+        assert_eq!(contact_points.len(), 2);
+        let mut xs: Vec<f32> = contact_points
+            .iter()
+            .map(|point| point.position.x)
+            .collect();
+        xs.sort_by(f32::total_cmp);
+        assert!((xs[0] + 30.).abs() < 1e-3, "points {xs:?}");
+        assert!((xs[1] - 30.).abs() < 1e-3, "points {xs:?}");
+        for point in contact_points.iter() {
+            assert!((point.depth - 2.).abs() < 1e-3, "depth {}", point.depth);
+            assert!((point.position.y - 49.).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn rotated_compound_aabb() {
+        let compound = Shape::Compound(CompoundParams {
+            children: vec![
+                CompoundChild::new(
+                    Vec2::new(30., 0.),
+                    FRAC_PI_2,
+                    Shape::Box(BoxParams::new(20., 10.)),
+                ),
+                CompoundChild::new(
+                    Vec2::new(-30., 10.),
+                    0.,
+                    Shape::Circle(CircleParams::new(5.)),
+                ),
+            ],
+            centroid: Vec2::ZERO,
+        });
+        // turned a quarter left the box lies flat at (0, 30), the circle
+        // moves to (-10, -30)
+        let transform =
+            Transform::from_xyz(100., 0., 0.).with_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        let aabb = compound.get_aabb(&transform);
+        assert!(
+            aabb.min.distance(Vec2::new(85., -35.)) < 1e-3,
+            "min {}",
+            aabb.min
+        );
+        assert!(
+            aabb.max.distance(Vec2::new(110., 35.)) < 1e-3,
+            "max {}",
+            aabb.max
+        );
+    }
+}
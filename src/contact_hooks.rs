@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+
+use crate::{collisions::ShapeContact, contacts::ContactCache};
+
+/// Edits contacts after the narrow phase, before they are solved. Hooks do
+/// not see the world, so they keep the entities they care about themselves.
+pub trait ContactHook: Send + Sync {
+    /// Called for every contact of the sub step. Clear `contact.enabled` to let
+    /// the bodies pass through each other, or change the normal, friction,
+    /// restitution or tangent speed.
+    fn modify_contact(&self, contact: &mut ShapeContact);
+}
+
+impl<F: Fn(&mut ShapeContact) + Send + Sync> ContactHook for F {
+    fn modify_contact(&self, contact: &mut ShapeContact) {
+        self(contact);
+    }
+}
+
+/// Hooks run by `world_step` in the order they were registered.
+#[derive(Resource, Default)]
+pub struct ContactHooks {
+    hooks: Vec<Box<dyn ContactHook>>,
+}
+
+impl ContactHooks {
+    pub fn register(&mut self, hook: impl ContactHook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+}
+
+/// Surface of `entity` moves along the x axis and carries the bodies on it.
+pub struct ConveyorBelt {
+    pub entity: Entity,
+    pub speed: f32,
+}
+
+impl ContactHook for ConveyorBelt {
+    fn modify_contact(&self, contact: &mut ShapeContact) {
+        // tangent speed is the speed of B relative to A
+        let tangent = contact.collision_normal.perp();
+        let belt_speed = self.speed * tangent.x;
+        if contact.entity_a == self.entity {
+            contact.tangent_speed = belt_speed;
+        } else if contact.entity_b == self.entity {
+            contact.tangent_speed = -belt_speed;
+        }
+    }
+}
+
+pub fn run_contact_hooks(hooks: &ContactHooks, contacts: &mut ContactCache) {
+    for contact in contacts.iter_mut() {
+        for hook in hooks.hooks.iter() {
+            hook.modify_contact(contact);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::{Collider, Shape},
+        flat_body::{BoxParams, FlatBody, FlatBodyType, on_flat_body_added},
+        flat_world::{FlatWorld, run_sub_steps},
+    };
+
+    /// Position and velocity of a box after a second on hooked ground.
+    fn step_box_on_ground(
+        ground_first: bool,
+        make_hooks: impl FnOnce(Entity) -> ContactHooks,
+    ) -> (Vec2, Vec2) {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld {
+            gravity: Vec2::new(0., -300.),
+            ..Default::default()
+        });
+        world.add_observer(on_flat_body_added);
+        let ground = (
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(400., 100.))),
+        );
+        let body = (
+            Transform::from_xyz(0., 75., 0.),
+            FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+            Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+        );
+        // the pair keeps the smaller entity as A, spawn order picks the side
+        let (ground, body) = if ground_first {
+            let ground = world.spawn(ground).id();
+            (ground, world.spawn(body).id())
+        } else {
+            let body = world.spawn(body).id();
+            (world.spawn(ground).id(), body)
+        };
+        world.insert_resource(make_hooks(ground));
+
+        run_sub_steps(&mut world, 60, 1. / 60.);
+
+        let position = world.get::<Transform>(body).unwrap().translation.truncate();
+        let velocity = world.get::<FlatBody>(body).unwrap().linear_velocity;
+        return (position, velocity);
+    }
+
+    #[test]
+    fn box_rests_without_hooks() {
+        let (position, velocity) = step_box_on_ground(true, |_ground| ContactHooks::default());
+        assert!((position.y - 75.).abs() < 1., "position {position}");
+        assert!(velocity.x.abs() < 1e-3, "velocity {velocity}");
+    }
+
+    #[test]
+    fn conveyor_belt_carries_box() {
+        for ground_first in [true, false] {
+            let (position, velocity) = step_box_on_ground(ground_first, |ground| {
+                let mut contact_hooks = ContactHooks::default();
+                contact_hooks.register(ConveyorBelt {
+                    entity: ground,
+                    speed: 40.,
+                });
+                return contact_hooks;
+            });
+            assert!((velocity.x - 40.).abs() < 1., "velocity {velocity}");
+            assert!(position.x > 20., "position {position}");
+            assert!((position.y - 75.).abs() < 1., "position {position}");
+        }
+    }
+
+    #[test]
+    fn disabled_contacts_are_not_solved() {
+        let (position, _velocity) = step_box_on_ground(true, |_ground| {
+            let mut contact_hooks = ContactHooks::default();
+            contact_hooks.register(|contact: &mut ShapeContact| contact.enabled = false);
+            return contact_hooks;
+        });
+        // falls about 150 in the second
+        assert!(position.y < 0., "position {position}");
+    }
+}
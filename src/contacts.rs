@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::collisions::ShapeContact;
+
+/// Entity pair with the smaller entity first, so both orders map to one key.
+pub fn pair_key(entity_a: Entity, entity_b: Entity) -> (Entity, Entity) {
+    if entity_b < entity_a {
+        return (entity_b, entity_a);
+    }
+    return (entity_a, entity_b);
+}
+
+/// Contacts of the current and previous step, sorted by entity pair.
+#[derive(Default)]
+pub struct ContactCache {
+    pairs: BTreeMap<(Entity, Entity), Vec<ShapeContact>>,
+    previous_pairs: BTreeMap<(Entity, Entity), Vec<ShapeContact>>,
+}
+
+impl ContactCache {
+    /// Current contacts become the previous ones, call once before each narrow phase.
+    pub fn begin_step(&mut self) {
+        std::mem::swap(&mut self.pairs, &mut self.previous_pairs);
+        self.pairs.clear();
+    }
+
+    /// Adds a contact, matched points keep their accumulated impulses.
+    pub fn insert(&mut self, mut contact: ShapeContact) {
+        let key = pair_key(contact.entity_a, contact.entity_b);
+
+        if let Some(previous) = self.previous_contact(&contact) {
+            for point in contact.contact_points.iter_mut() {
+                let Some(previous_point) = previous
+                    .contact_points
+                    .iter()
+                    .find(|previous_point| previous_point.id == point.id)
+                else {
+                    continue;
+                };
+                point.persisted = true;
+                point.normal_impulse = previous_point.normal_impulse;
+                point.tangent_impulse = previous_point.tangent_impulse;
+            }
+        }
+
+        self.pairs.entry(key).or_default().push(contact);
+    }
+
+    /// Contact of the previous step between the same sub-shapes.
+    pub fn previous_contact(&self, contact: &ShapeContact) -> Option<&ShapeContact> {
+        let key = pair_key(contact.entity_a, contact.entity_b);
+        return self.previous_pairs.get(&key)?.iter().find(|previous| {
+            previous.sub_shape_a == contact.sub_shape_a
+                && previous.sub_shape_b == contact.sub_shape_b
+        });
+    }
+
+    /// Entity pairs where a sensor overlaps another collider in the current step.
+    pub fn sensor_overlaps(&self) -> impl Iterator<Item = (Entity, Entity)> {
+        return self
+            .pairs
+            .iter()
+            .filter(|(_, contacts)| contacts.iter().any(|contact| contact.is_sensor))
+            .map(|(pair, _)| *pair);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ShapeContact> {
+        return self.pairs.values().flatten();
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut ShapeContact> {
+        return self.pairs.values_mut().flatten();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collisions::{ContactId, ContactPoint};
+
+    fn contact(sub_shape_a: usize, ids: &[ContactId]) -> ShapeContact {
+        return ShapeContact {
+            entity_a: Entity::from_raw_u32(1).unwrap(),
+            entity_b: Entity::from_raw_u32(2).unwrap(),
+            sub_shape_a,
+            sub_shape_b: 0,
+            collision_normal: Vec2::Y,
+            penetration_depth: 1.,
+            contact_points: ids
+                .iter()
+                .map(|id| ContactPoint::new(Vec2::ZERO, 1., *id))
+                .collect(),
+            is_sensor: false,
+            enabled: true,
+            static_friction: 0.,
+            dynamic_friction: 0.,
+            restitution: 0.,
+            tangent_speed: 0.,
+        };
+    }
+
+    #[test]
+    fn points_keep_impulses_by_feature_id() {
+        let mut contacts = ContactCache::default();
+        contacts.begin_step();
+        contacts.insert(contact(0, &[1, 2]));
+        contacts.insert(contact(1, &[1]));
+        for contact in contacts.iter_mut() {
+            for point in contact.contact_points.iter_mut() {
+                point.normal_impulse = point.id as f32 * 10.;
+                point.tangent_impulse = -1.;
+            }
+        }
+
+        contacts.begin_step();
+        // point 1 of sub-shape 0 ended, point 3 is new
+        contacts.insert(contact(0, &[2, 3]));
+        contacts.insert(contact(2, &[1]));
+
+        let points: Vec<_> = contacts
+            .iter()
+            .flat_map(|contact| {
+                contact
+                    .contact_points
+                    .iter()
+                    .map(|point| (contact.sub_shape_a, point.id, point.persisted))
+            })
+            .collect();
+        assert_eq!(points, [(0, 2, true), (0, 3, false), (2, 1, false)]);
+
+        let persisted = contacts.iter().next().unwrap().contact_points[0];
+        assert_eq!(persisted.normal_impulse, 20.);
+        assert_eq!(persisted.tangent_impulse, -1.);
+        // a matching id on another sub-shape does not count
+        let other_piece = contacts.iter().nth(1).unwrap().contact_points[0];
+        assert_eq!(other_piece.normal_impulse, 0.);
+
+        // both entity orders are the same pair
+        let mut swapped = contact(0, &[2]);
+        std::mem::swap(&mut swapped.entity_a, &mut swapped.entity_b);
+        assert!(contacts.previous_contact(&swapped).is_some());
+    }
+}
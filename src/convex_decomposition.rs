@@ -0,0 +1,241 @@
+use bevy::math::Vec2;
+
+const COLLINEAR_TOLERANCE: f32 = 1e-5;
+/// Relative difference allowed between the areas of triangles and outline.
+const AREA_TOLERANCE: f32 = 1e-3;
+
+fn signed_area(points: &[Vec2]) -> f32 {
+    let mut area = 0.;
+    for i in 0..points.len() {
+        area += points[i].perp_dot(points[(i + 1) % points.len()]);
+    }
+    return area / 2.;
+}
+
+/// Is `c` to the left of the line going from `a` to `b`.
+fn is_left(a: Vec2, b: Vec2, c: Vec2) -> bool {
+    return (b - a).perp_dot(c - b) > COLLINEAR_TOLERANCE;
+}
+
+fn point_in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let d1 = (b - a).perp_dot(p - a);
+    let d2 = (c - b).perp_dot(p - b);
+    let d3 = (a - c).perp_dot(p - c);
+
+    return d1 >= 0. && d2 >= 0. && d3 >= 0.;
+}
+
+fn is_convex(points: &[Vec2], indices: &[usize]) -> bool {
+    let len = indices.len();
+    for i in 0..len {
+        let a = points[indices[i]];
+        let b = points[indices[(i + 1) % len]];
+        let c = points[indices[(i + 2) % len]];
+        if (b - a).perp_dot(c - b) < -COLLINEAR_TOLERANCE {
+            return false;
+        }
+    }
+    return true;
+}
+
+/// Ear clipping triangulation of a simple polygon into indices of `points`.
+pub fn triangulate(points: &[Vec2]) -> Option<Vec<[usize; 3]>> {
+    if points.len() < 3 {
+        return None;
+    }
+    let mut indices: Vec<usize> = (0..points.len()).collect();
+    if signed_area(points) < 0. {
+        indices.reverse();
+    }
+
+    let mut triangles = Vec::new();
+    while indices.len() > 3 {
+        let len = indices.len();
+        let mut ear = None;
+
+        for i in 0..len {
+            let prev = indices[(i + len - 1) % len];
+            let current = indices[i];
+            let next = indices[(i + 1) % len];
+            let (a, b, c) = (points[prev], points[current], points[next]);
+
+            if !is_left(a, b, c) {
+                // reflex or collinear vertex can not be an ear
+                continue;
+            }
+
+            let contains_other = indices.iter().any(|&other| {
+                other != prev
+                    && other != current
+                    && other != next
+                    && point_in_triangle(points[other], a, b, c)
+            });
+            if contains_other {
+                continue;
+            }
+
+            ear = Some(i);
+            break;
+        }
+
+        let Some(i) = ear else {
+            // self intersecting or degenerate outline
+            return None;
+        };
+
+        triangles.push([
+            indices[(i + len - 1) % len],
+            indices[i],
+            indices[(i + 1) % len],
+        ]);
+        indices.remove(i);
+    }
+
+    triangles.push([indices[0], indices[1], indices[2]]);
+
+    // ears of self intersecting outlines can overlap or leave parts uncovered
+    let outline_area = signed_area(points).abs();
+    let triangles_area: f32 = triangles
+        .iter()
+        .map(|&[a, b, c]| signed_area(&[points[a], points[b], points[c]]).abs())
+        .sum();
+    if outline_area <= f32::EPSILON
+        || (triangles_area - outline_area).abs() > outline_area * AREA_TOLERANCE
+    {
+        return None;
+    }
+
+    return Some(triangles);
+}
+
+/// Returns pieces `a` and `b` glued along their shared edge, if they share one.
+fn merge_pieces(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    for i in 0..a.len() {
+        let u = a[i];
+        let v = a[(i + 1) % a.len()];
+
+        // neighbouring counter clockwise piece walks the shared edge backwards
+        let Some(j) = (0..b.len()).find(|&j| b[j] == v && b[(j + 1) % b.len()] == u) else {
+            continue;
+        };
+
+        // walk `a` from v around to u, then `b` from u around to v without the ends
+        let mut merged: Vec<usize> = (0..a.len()).map(|k| a[(i + 1 + k) % a.len()]).collect();
+        merged.extend((2..b.len()).map(|k| b[(j + k) % b.len()]));
+        return Some(merged);
+    }
+    return None;
+}
+
+/// Hertel-Mehlhorn split of a simple polygon into convex pieces.
+pub fn decompose(points: &[Vec2]) -> Option<Vec<Vec<Vec2>>> {
+    let mut pieces: Vec<Vec<usize>> = triangulate(points)?
+        .into_iter()
+        .map(|triangle| triangle.to_vec())
+        .collect();
+
+    let mut merged_any = true;
+    while merged_any {
+        merged_any = false;
+
+        'search: for i in 0..pieces.len() {
+            for j in (i + 1)..pieces.len() {
+                let Some(merged) = merge_pieces(&pieces[i], &pieces[j]) else {
+                    continue;
+                };
+                if !is_convex(points, &merged) {
+                    continue;
+                }
+
+                pieces[i] = merged;
+                pieces.remove(j);
+                merged_any = true;
+                break 'search;
+            }
+        }
+    }
+
+    return Some(
+        pieces
+            .into_iter()
+            .map(|piece| piece.into_iter().map(|i| points[i]).collect())
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::Shape,
+        flat_body::{CompoundParams, shape_centroid},
+    };
+
+    fn bowl() -> Vec<Vec2> {
+        vec![
+            Vec2::new(-120., 0.),
+            Vec2::new(120., 0.),
+            Vec2::new(120., 80.),
+            Vec2::new(90., 80.),
+            Vec2::new(90., 30.),
+            Vec2::new(-90., 30.),
+            Vec2::new(-90., 80.),
+            Vec2::new(-120., 80.),
+        ]
+    }
+
+    #[test]
+    fn pieces_cover_concave_outline() {
+        for outline in [bowl(), bowl().into_iter().rev().collect()] {
+            let pieces = decompose(&outline).unwrap();
+            assert!(pieces.len() > 1);
+
+            let pieces_area: f32 = pieces.iter().map(|piece| signed_area(piece)).sum();
+            let outline_area = signed_area(&outline).abs();
+            assert!(
+                (pieces_area - outline_area).abs() < 1e-2,
+                "pieces {pieces_area} outline {outline_area}"
+            );
+            for piece in pieces.iter() {
+                let indices: Vec<usize> = (0..piece.len()).collect();
+                assert!(is_convex(piece, &indices));
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_outlines_fail() {
+        let collinear = [
+            Vec2::ZERO,
+            Vec2::new(1., 0.),
+            Vec2::new(2., 0.),
+            Vec2::new(3., 0.),
+        ];
+        assert!(triangulate(&collinear).is_none());
+
+        let bow_tie = [
+            Vec2::ZERO,
+            Vec2::new(100., 100.),
+            Vec2::new(100., 0.),
+            Vec2::new(0., 100.),
+        ];
+        assert!(decompose(&bow_tie).is_none());
+
+        assert!(triangulate(&[Vec2::ZERO, Vec2::X]).is_none());
+    }
+
+    #[test]
+    fn compound_from_outline_is_centered() {
+        let compound_params = CompoundParams::from_concave_polygon(&bowl()).unwrap();
+        let centroid = shape_centroid(&Shape::Compound(compound_params.clone()));
+        assert!(centroid.length() < 1e-3, "centroid {centroid}");
+
+        // two posts on a base, the base pulls the centroid down
+        let expected = Vec2::new(0., (7200. * 15. + 3000. * 55.) / 10200.);
+        assert!(
+            compound_params.centroid.distance(expected) < 1e-2,
+            "offset {}",
+            compound_params.centroid
+        );
+    }
+}
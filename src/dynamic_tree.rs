@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{collisions::intersect_aabbs, flat_aabb::FlatAABB};
+
+const NULL_NODE: usize = usize::MAX;
+
+struct TreeNode {
+    /// Fattened AABB for leaves, union of both children otherwise.
+    aabb: FlatAABB,
+    parent: usize,
+    child1: usize,
+    child2: usize,
+    /// Leaf is 0, free node is -1.
+    height: i32,
+    entity: Option<Entity>,
+    /// Real AABB of the body, only set on leaves.
+    tight_aabb: FlatAABB,
+    is_static: bool,
+}
+
+impl TreeNode {
+    fn is_leaf(&self) -> bool {
+        return self.child1 == NULL_NODE;
+    }
+}
+
+/// Bounding volume tree of fattened AABBs, like Box2D's `b2DynamicTree`.
+pub struct DynamicTree {
+    nodes: Vec<TreeNode>,
+    root: usize,
+    free_list: Vec<usize>,
+    proxies: HashMap<Entity, usize>,
+    /// How much leaf AABBs are grown on every side.
+    pub margin: f32,
+}
+
+impl Default for DynamicTree {
+    fn default() -> Self {
+        return DynamicTree::new(10.);
+    }
+}
+
+impl DynamicTree {
+    pub fn new(margin: f32) -> Self {
+        return DynamicTree {
+            nodes: Vec::new(),
+            root: NULL_NODE,
+            free_list: Vec::new(),
+            proxies: HashMap::new(),
+            margin,
+        };
+    }
+
+    fn allocate_node(&mut self) -> usize {
+        let node = TreeNode {
+            aabb: FlatAABB::default(),
+            parent: NULL_NODE,
+            child1: NULL_NODE,
+            child2: NULL_NODE,
+            height: 0,
+            entity: None,
+            tight_aabb: FlatAABB::default(),
+            is_static: false,
+        };
+        if let Some(index) = self.free_list.pop() {
+            self.nodes[index] = node;
+            return index;
+        }
+        self.nodes.push(node);
+        return self.nodes.len() - 1;
+    }
+
+    fn free_node(&mut self, index: usize) {
+        self.nodes[index].height = -1;
+        self.nodes[index].entity = None;
+        self.free_list.push(index);
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        return self.proxies.contains_key(&entity);
+    }
+
+    pub fn insert(&mut self, entity: Entity, aabb: FlatAABB, is_static: bool) {
+        if self.contains(entity) {
+            self.remove(entity);
+        }
+
+        let leaf = self.allocate_node();
+        self.nodes[leaf].aabb = aabb.expanded(self.margin);
+        self.nodes[leaf].tight_aabb = aabb;
+        self.nodes[leaf].entity = Some(entity);
+        self.nodes[leaf].is_static = is_static;
+        self.insert_leaf(leaf);
+        self.proxies.insert(entity, leaf);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(leaf) = self.proxies.remove(&entity) else {
+            return;
+        };
+        self.remove_leaf(leaf);
+        self.free_node(leaf);
+    }
+
+    /// Updates the entity AABB, true when the leaf had to be reinserted.
+    pub fn update(&mut self, entity: Entity, aabb: FlatAABB, is_static: bool) -> bool {
+        let Some(&leaf) = self.proxies.get(&entity) else {
+            self.insert(entity, aabb, is_static);
+            return true;
+        };
+
+        self.nodes[leaf].tight_aabb = aabb;
+        self.nodes[leaf].is_static = is_static;
+        if self.nodes[leaf].aabb.contains(&aabb) {
+            return false;
+        }
+
+        self.remove_leaf(leaf);
+        self.nodes[leaf].aabb = aabb.expanded(self.margin);
+        self.insert_leaf(leaf);
+        return true;
+    }
+
+    /// Removes every entity for which `keep` returns false.
+    pub fn retain(&mut self, keep: impl Fn(Entity) -> bool) {
+        let removed: Vec<Entity> = self
+            .proxies
+            .keys()
+            .filter(|entity| !keep(**entity))
+            .copied()
+            .collect();
+        for entity in removed {
+            self.remove(entity);
+        }
+    }
+
+    /// Calls `callback` for every entity whose fattened AABB overlaps `aabb`.
+    pub fn query(&self, aabb: &FlatAABB, mut callback: impl FnMut(Entity, &FlatAABB)) {
+        if self.root == NULL_NODE {
+            return;
+        }
+
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.aabb.overlaps(aabb) {
+                continue;
+            }
+            if node.is_leaf() {
+                if let Some(entity) = node.entity {
+                    callback(entity, &node.tight_aabb);
+                }
+                continue;
+            }
+            stack.push(node.child1);
+            stack.push(node.child2);
+        }
+    }
+
+    /// Sorted pairs of overlapping non static entities accepted by `filter`.
+    pub fn find_pairs(&self, filter: impl Fn(Entity, Entity) -> bool) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for (&entity, &leaf) in self.proxies.iter() {
+            let node = &self.nodes[leaf];
+            if node.is_static {
+                continue;
+            }
+            self.query(&node.tight_aabb, |other, other_aabb| {
+                if other == entity {
+                    return;
+                }
+                let other_is_static = self.nodes[self.proxies[&other]].is_static;
+                // pairs of two moving bodies are found from both sides, keep one
+                if !other_is_static && other < entity {
+                    return;
+                }
+                if !filter(entity, other) {
+                    return;
+                }
+                if !intersect_aabbs(&node.tight_aabb, other_aabb) {
+                    return;
+                }
+                pairs.push((entity.min(other), entity.max(other)));
+            });
+        }
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL_NODE {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL_NODE;
+            return;
+        }
+
+        // find the best sibling by the surface area heuristic
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut index = self.root;
+        while !self.nodes[index].is_leaf() {
+            let node = &self.nodes[index];
+            let area = node.aabb.perimeter();
+            let combined_area = node.aabb.union(&leaf_aabb).perimeter();
+
+            // cost of a new parent for this node and the leaf
+            let cost = 2. * combined_area;
+            // cost of pushing the leaf further down the tree
+            let inheritance_cost = 2. * (combined_area - area);
+
+            let child_cost = |child: usize| {
+                let child_aabb = &self.nodes[child].aabb;
+                let union_area = leaf_aabb.union(child_aabb).perimeter();
+                if self.nodes[child].is_leaf() {
+                    return union_area + inheritance_cost;
+                }
+                return union_area - child_aabb.perimeter() + inheritance_cost;
+            };
+            let cost1 = child_cost(node.child1);
+            let cost2 = child_cost(node.child2);
+
+            if cost < cost1 && cost < cost2 {
+                break;
+            }
+            index = if cost1 < cost2 {
+                node.child1
+            } else {
+                node.child2
+            };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate_node();
+        self.nodes[new_parent].parent = old_parent;
+        self.nodes[new_parent].aabb = leaf_aabb.union(&self.nodes[sibling].aabb);
+        self.nodes[new_parent].height = self.nodes[sibling].height + 1;
+        self.nodes[new_parent].child1 = sibling;
+        self.nodes[new_parent].child2 = leaf;
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+
+        if old_parent == NULL_NODE {
+            self.root = new_parent;
+        } else if self.nodes[old_parent].child1 == sibling {
+            self.nodes[old_parent].child1 = new_parent;
+        } else {
+            self.nodes[old_parent].child2 = new_parent;
+        }
+
+        self.refit(self.nodes[leaf].parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL_NODE;
+            return;
+        }
+
+        let parent = self.nodes[leaf].parent;
+        let grand_parent = self.nodes[parent].parent;
+        let sibling = if self.nodes[parent].child1 == leaf {
+            self.nodes[parent].child2
+        } else {
+            self.nodes[parent].child1
+        };
+
+        self.free_node(parent);
+        if grand_parent == NULL_NODE {
+            self.root = sibling;
+            self.nodes[sibling].parent = NULL_NODE;
+            return;
+        }
+
+        if self.nodes[grand_parent].child1 == parent {
+            self.nodes[grand_parent].child1 = sibling;
+        } else {
+            self.nodes[grand_parent].child2 = sibling;
+        }
+        self.nodes[sibling].parent = grand_parent;
+        self.refit(grand_parent);
+    }
+
+    /// Walks up from `index` balancing nodes and fixing heights and AABBs.
+    fn refit(&mut self, mut index: usize) {
+        while index != NULL_NODE {
+            index = self.balance(index);
+
+            let child1 = self.nodes[index].child1;
+            let child2 = self.nodes[index].child2;
+            self.nodes[index].height = 1 + self.nodes[child1].height.max(self.nodes[child2].height);
+            self.nodes[index].aabb = self.nodes[child1].aabb.union(&self.nodes[child2].aabb);
+
+            index = self.nodes[index].parent;
+        }
+    }
+
+    /// Rotates the higher child of `a` up, returns the node now in its place.
+    fn balance(&mut self, a: usize) -> usize {
+        if self.nodes[a].is_leaf() || self.nodes[a].height < 2 {
+            return a;
+        }
+
+        let b = self.nodes[a].child1;
+        let c = self.nodes[a].child2;
+        let balance = self.nodes[c].height - self.nodes[b].height;
+
+        if balance > 1 {
+            self.rotate_up(a, c, b, false);
+            return c;
+        }
+        if balance < -1 {
+            self.rotate_up(a, b, c, true);
+            return b;
+        }
+        return a;
+    }
+
+    /// Moves `up`, a child of `a`, into the place of `a`.
+    fn rotate_up(&mut self, a: usize, up: usize, other: usize, up_is_child1: bool) {
+        let f = self.nodes[up].child1;
+        let g = self.nodes[up].child2;
+
+        // `up` takes the place of `a` under its parent
+        let parent = self.nodes[a].parent;
+        self.nodes[up].child1 = a;
+        self.nodes[up].parent = parent;
+        self.nodes[a].parent = up;
+        if parent == NULL_NODE {
+            self.root = up;
+        } else if self.nodes[parent].child1 == a {
+            self.nodes[parent].child1 = up;
+        } else {
+            self.nodes[parent].child2 = up;
+        }
+
+        let (keep, give) = if self.nodes[f].height > self.nodes[g].height {
+            (f, g)
+        } else {
+            (g, f)
+        };
+        self.nodes[up].child2 = keep;
+        if up_is_child1 {
+            self.nodes[a].child1 = give;
+        } else {
+            self.nodes[a].child2 = give;
+        }
+        self.nodes[give].parent = a;
+
+        self.nodes[a].aabb = self.nodes[other].aabb.union(&self.nodes[give].aabb);
+        self.nodes[up].aabb = self.nodes[a].aabb.union(&self.nodes[keep].aabb);
+        self.nodes[a].height = 1 + self.nodes[other].height.max(self.nodes[give].height);
+        self.nodes[up].height = 1 + self.nodes[a].height.max(self.nodes[keep].height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::{Collider, Shape},
+        flat_body::{BoxParams, FlatBody, FlatBodyType},
+        flat_world::{FlatWorld, run_sub_steps},
+    };
+
+    fn entity(index: u32) -> Entity {
+        return Entity::from_raw_u32(index).unwrap();
+    }
+
+    fn assert_same_aabb(a: &FlatAABB, b: &FlatAABB) {
+        assert!(a.min == b.min && a.max == b.max, "{:?} is not {:?}", a, b);
+    }
+
+    /// Number of leaves below `index`, panics on a broken node.
+    fn assert_valid(tree: &DynamicTree, index: usize) -> usize {
+        let node = &tree.nodes[index];
+        assert!(node.height >= 0, "node {index} is free");
+        if node.is_leaf() {
+            assert_eq!(node.height, 0);
+            let entity = node.entity.expect("leaf has an entity");
+            assert_eq!(tree.proxies[&entity], index);
+            assert!(node.aabb.contains(&node.tight_aabb));
+            return 1;
+        }
+
+        for child in [node.child1, node.child2] {
+            assert_eq!(tree.nodes[child].parent, index);
+            assert!(node.aabb.contains(&tree.nodes[child].aabb));
+        }
+        let height1 = tree.nodes[node.child1].height;
+        let height2 = tree.nodes[node.child2].height;
+        assert_eq!(node.height, 1 + height1.max(height2));
+        return assert_valid(tree, node.child1) + assert_valid(tree, node.child2);
+    }
+
+    fn assert_valid_tree(tree: &DynamicTree) {
+        if tree.root == NULL_NODE {
+            assert!(tree.proxies.is_empty());
+            return;
+        }
+        assert_eq!(tree.nodes[tree.root].parent, NULL_NODE);
+        assert_eq!(assert_valid(tree, tree.root), tree.proxies.len());
+        // every node is either in the tree or free
+        assert_eq!(
+            tree.nodes.len() - tree.free_list.len(),
+            2 * tree.proxies.len() - 1
+        );
+    }
+
+    #[test]
+    fn insert_stores_fattened_leaf() {
+        let mut tree = DynamicTree::new(10.);
+        let aabb = FlatAABB::new(0., 0., 20., 20.);
+        assert!(tree.update(entity(0), aabb, false));
+
+        let leaf = tree.proxies[&entity(0)];
+        assert_eq!(tree.root, leaf);
+        assert_same_aabb(&tree.nodes[leaf].aabb, &FlatAABB::new(-10., -10., 30., 30.));
+        assert_same_aabb(&tree.nodes[leaf].tight_aabb, &aabb);
+        assert_valid_tree(&tree);
+
+        for i in 1..20 {
+            let offset = 50. * i as f32;
+            tree.insert(
+                entity(i),
+                FlatAABB::new(offset, 0., offset + 20., 20.),
+                i % 4 == 0,
+            );
+            assert_valid_tree(&tree);
+        }
+    }
+
+    #[test]
+    fn moves_inside_the_fat_aabb_keep_the_leaf() {
+        let mut tree = DynamicTree::new(10.);
+        for i in 0..8 {
+            let offset = 50. * i as f32;
+            tree.insert(
+                entity(i),
+                FlatAABB::new(offset, 0., offset + 20., 20.),
+                false,
+            );
+        }
+        let leaf = tree.proxies[&entity(3)];
+        let fat_aabb = tree.nodes[leaf].aabb;
+
+        let moved = FlatAABB::new(158., -8., 178., 12.);
+        assert!(!tree.update(entity(3), moved, false));
+        assert_eq!(tree.proxies[&entity(3)], leaf);
+        assert_same_aabb(&tree.nodes[leaf].aabb, &fat_aabb);
+        // the real AABB follows the body, pairs use it
+        assert_same_aabb(&tree.nodes[leaf].tight_aabb, &moved);
+        assert_valid_tree(&tree);
+    }
+
+    #[test]
+    fn moves_out_of_the_fat_aabb_reinsert() {
+        let mut tree = DynamicTree::new(10.);
+        for i in 0..8 {
+            let offset = 50. * i as f32;
+            tree.insert(
+                entity(i),
+                FlatAABB::new(offset, 0., offset + 20., 20.),
+                false,
+            );
+        }
+
+        let moved = FlatAABB::new(150., 300., 170., 320.);
+        assert!(tree.update(entity(3), moved, false));
+        let leaf = tree.proxies[&entity(3)];
+        assert_same_aabb(&tree.nodes[leaf].aabb, &moved.expanded(10.));
+        assert_same_aabb(&tree.nodes[leaf].tight_aabb, &moved);
+        assert_valid_tree(&tree);
+
+        let mut found = Vec::new();
+        tree.query(&FlatAABB::new(155., 305., 160., 310.), |entity, _aabb| {
+            found.push(entity)
+        });
+        assert_eq!(found, [entity(3)]);
+    }
+
+    #[test]
+    fn removed_entities_leave_the_tree() {
+        let mut tree = DynamicTree::new(10.);
+        for i in 0..8 {
+            let offset = 50. * i as f32;
+            tree.insert(
+                entity(i),
+                FlatAABB::new(offset, 0., offset + 20., 20.),
+                false,
+            );
+        }
+
+        tree.remove(entity(2));
+        assert!(!tree.contains(entity(2)));
+        assert_valid_tree(&tree);
+        // removing twice does nothing
+        tree.remove(entity(2));
+        assert_valid_tree(&tree);
+
+        tree.retain(|entity| entity.index_u32() % 2 == 0);
+        assert_eq!(tree.proxies.len(), 3);
+        assert_valid_tree(&tree);
+        // freed nodes are reused
+        let node_count = tree.nodes.len();
+        tree.insert(entity(9), FlatAABB::new(0., 100., 20., 120.), false);
+        assert_eq!(tree.nodes.len(), node_count);
+        assert_valid_tree(&tree);
+
+        tree.retain(|_entity| false);
+        assert_eq!(tree.root, NULL_NODE);
+        assert_valid_tree(&tree);
+    }
+
+    #[test]
+    fn despawned_bodies_leave_the_tree() {
+        let mut world = World::new();
+        let bodies: Vec<Entity> = (0..3)
+            .map(|i| {
+                world
+                    .spawn((
+                        Transform::from_xyz(100. * i as f32, 0., 0.),
+                        FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                        Collider::new(Shape::Box(BoxParams::new(20., 20.))),
+                    ))
+                    .id()
+            })
+            .collect();
+        run_sub_steps(&mut world, 1, 1. / 60.);
+        assert!(
+            world
+                .resource::<FlatWorld>()
+                .dynamic_tree
+                .contains(bodies[1])
+        );
+
+        world.despawn(bodies[1]);
+        run_sub_steps(&mut world, 1, 1. / 60.);
+
+        let dynamic_tree = &world.resource::<FlatWorld>().dynamic_tree;
+        assert!(!dynamic_tree.contains(bodies[1]));
+        assert!(dynamic_tree.contains(bodies[0]) && dynamic_tree.contains(bodies[2]));
+        assert_valid_tree(dynamic_tree);
+    }
+}
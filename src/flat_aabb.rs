@@ -1,5 +1,6 @@
 use bevy::math::Vec2;
 
+#[derive(Clone, Copy, Debug)]
 pub struct FlatAABB {
     pub min: Vec2,
     pub max: Vec2,
@@ -12,6 +13,50 @@ impl FlatAABB {
             max: Vec2::new(max_x, max_y),
         }
     }
+
+    pub fn union(&self, other: &FlatAABB) -> FlatAABB {
+        FlatAABB {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn perimeter(&self) -> f32 {
+        let size = self.max - self.min;
+        2. * (size.x + size.y)
+    }
+
+    /// Is `other` completely inside.
+    pub fn contains(&self, other: &FlatAABB) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && other.max.x <= self.max.x
+            && other.max.y <= self.max.y
+    }
+
+    /// Overlap test which also counts touching boxes.
+    pub fn overlaps(&self, other: &FlatAABB) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+
+    /// Box grown by `margin` on every side.
+    pub fn expanded(&self, margin: f32) -> FlatAABB {
+        FlatAABB {
+            min: self.min - Vec2::splat(margin),
+            max: self.max + Vec2::splat(margin),
+        }
+    }
+
+    /// Box covering this one and this one moved by `motion`.
+    pub fn swept(&self, motion: Vec2) -> FlatAABB {
+        FlatAABB {
+            min: self.min.min(self.min + motion),
+            max: self.max.max(self.max + motion),
+        }
+    }
 }
 
 impl Default for FlatAABB {
@@ -2,7 +2,10 @@ use std::f32;
 
 use bevy::prelude::*;
 
-use crate::collisions::{Collider, Shape};
+use crate::{
+    collisions::{Collider, Shape},
+    convex_decomposition::decompose,
+};
 
 #[derive(Default, Debug)]
 pub enum FlatBodyType {
@@ -25,6 +28,8 @@ pub struct FlatBody {
     static_friction: f32,
     dynamic_friction: f32,
     pub body_type: FlatBodyType,
+    /// Sweep small fast bodies against static ones so they can not pass through.
+    pub ccd: bool,
 }
 
 impl FlatBody {
@@ -61,7 +66,8 @@ impl FlatBody {
             self.inv_inertia = 0.;
         } else {
             self.inertia = inertia;
-            self.inv_inertia = 1. / inertia;
+            // shapes without area have no inertia, they do not turn
+            self.inv_inertia = if inertia > 0. { 1. / inertia } else { 0. };
         }
     }
 
@@ -92,6 +98,7 @@ pub fn on_flat_body_added(
     };
     flat_body.update_inertia(collider);
 }
+#[derive(Clone)]
 pub struct CircleParams {
     pub radius: f32,
 }
@@ -102,7 +109,7 @@ impl CircleParams {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct BoxParams {
     pub width: f32,
     pub height: f32,
@@ -131,15 +138,384 @@ impl BoxParams {
     }
 }
 
+/// Vertical capsule laid out like bevy's `Capsule2d`.
+#[derive(Clone)]
+pub struct CapsuleParams {
+    pub radius: f32,
+    pub half_length: f32,
+}
+
+impl CapsuleParams {
+    pub fn new(radius: f32, length: f32) -> Self {
+        CapsuleParams {
+            radius,
+            half_length: length / 2.,
+        }
+    }
+
+    /// Returns global end points of the inner segment.
+    pub fn segment(&self, transform: &Transform) -> (Vec2, Vec2) {
+        let offset = transform.rotation.mul_vec3(Vec3::Y * self.half_length);
+        let a = transform.translation - offset;
+        let b = transform.translation + offset;
+        (Vec2::new(a.x, a.y), Vec2::new(b.x, b.y))
+    }
+}
+
+/// Turns sharper than this against the winding make a polygon concave.
+const CONVEX_TOLERANCE: f32 = 1e-5;
+
+/// Convex polygon around the local origin.
+#[derive(Default, Clone)]
+pub struct PolygonParams {
+    pub verticies: Vec<Vec2>,
+}
+
+impl PolygonParams {
+    /// `None` if not convex, see `CompoundParams::from_concave_polygon`.
+    pub fn new(verticies: Vec<Vec2>) -> Option<Self> {
+        if !is_convex_polygon(&verticies) {
+            return None;
+        }
+        Some(PolygonParams { verticies })
+    }
+
+    /// Regular polygon with `sides` vertices lying on a circle of `radius`.
+    pub fn regular(sides: usize, radius: f32) -> Self {
+        let verticies = (0..sides)
+            .map(|i| {
+                let angle = f32::consts::TAU * i as f32 / sides as f32;
+                Vec2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect();
+
+        PolygonParams { verticies }
+    }
+}
+
+/// Turns one way and goes around exactly once.
+fn is_convex_polygon(verticies: &[Vec2]) -> bool {
+    let len = verticies.len();
+    if len < 3 {
+        return false;
+    }
+
+    let mut area = 0.;
+    for i in 0..len {
+        area += verticies[i].perp_dot(verticies[(i + 1) % len]);
+    }
+    if area.abs() <= f32::EPSILON {
+        return false;
+    }
+
+    let mut turning = 0.;
+    for i in 0..len {
+        let a = verticies[i];
+        let b = verticies[(i + 1) % len];
+        let c = verticies[(i + 2) % len];
+        if a == b {
+            return false;
+        }
+        if (b - a).perp_dot(c - b) * area.signum() < -CONVEX_TOLERANCE {
+            return false;
+        }
+        turning += (b - a).angle_to(c - b);
+    }
+    return (turning.abs() - f32::consts::TAU).abs() < 1e-3;
+}
+
+/// Two sided line segment, ghost vertices are its chain neighbours.
+#[derive(Default, Clone)]
+pub struct SegmentParams {
+    pub verticies: [Vec2; 2],
+    pub ghost_start: Option<Vec2>,
+    pub ghost_end: Option<Vec2>,
+}
+
+impl SegmentParams {
+    pub fn new(a: Vec2, b: Vec2) -> Self {
+        SegmentParams {
+            verticies: [a, b],
+            ..Default::default()
+        }
+    }
+}
+
+/// Open polyline for static terrain, collided segment by segment.
+#[derive(Default, Clone)]
+pub struct ChainParams {
+    pub verticies: Vec<Vec2>,
+}
+
+impl ChainParams {
+    pub fn new(verticies: Vec<Vec2>) -> Self {
+        ChainParams { verticies }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.verticies.len().saturating_sub(1)
+    }
+
+    pub fn segment(&self, index: usize) -> SegmentParams {
+        SegmentParams {
+            verticies: [self.verticies[index], self.verticies[index + 1]],
+            ghost_start: index.checked_sub(1).map(|i| self.verticies[i]),
+            ghost_end: self.verticies.get(index + 2).copied(),
+        }
+    }
+}
+
+/// Child of a compound shape relative to the compound origin.
+#[derive(Clone)]
+pub struct CompoundChild {
+    pub offset: Vec2,
+    pub rotation: f32,
+    pub shape: Shape,
+}
+
+impl CompoundChild {
+    pub fn new(offset: Vec2, rotation: f32, shape: Shape) -> Self {
+        CompoundChild {
+            offset,
+            rotation,
+            shape,
+        }
+    }
+
+    pub fn local_transform(&self) -> Transform {
+        Transform::from_xyz(self.offset.x, self.offset.y, 0.)
+            .with_rotation(Quat::from_rotation_z(self.rotation))
+    }
+
+    /// Converts point from child space to compound space.
+    pub fn local_point(&self, point: Vec2) -> Vec2 {
+        self.offset + Vec2::from_angle(self.rotation).rotate(point)
+    }
+}
+
+/// Several shapes moving as one body.
+#[derive(Default, Clone)]
+pub struct CompoundParams {
+    pub children: Vec<CompoundChild>,
+    /// Centroid of the children as passed in, place the body there.
+    pub centroid: Vec2,
+}
+
+impl CompoundParams {
+    /// Moves the children to put their centroid at the origin.
+    pub fn new(children: Vec<CompoundChild>) -> Self {
+        let mut compound_params = CompoundParams {
+            children,
+            centroid: Vec2::ZERO,
+        };
+        let centroid = shape_centroid(&Shape::Compound(compound_params.clone()));
+        for child in compound_params.children.iter_mut() {
+            child.offset -= centroid;
+        }
+        compound_params.centroid = centroid;
+        return compound_params;
+    }
+
+    /// Convex pieces of a simple outline, `None` if it is not simple.
+    pub fn from_concave_polygon(verticies: &[Vec2]) -> Option<Self> {
+        let children = decompose(verticies)?
+            .into_iter()
+            .map(|piece| {
+                let polygon_params = PolygonParams::new(piece)?;
+                Some(CompoundChild::new(
+                    Vec2::ZERO,
+                    0.,
+                    Shape::Polygon(polygon_params),
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(CompoundParams::new(children))
+    }
+}
+
+/// Rotational inertia of a rod between `a` and `b` around the local origin.
+fn segment_rotational_inertia(mass: f32, a: Vec2, b: Vec2) -> f32 {
+    return mass * (a.dot(a) + a.dot(b) + b.dot(b)) / 3.;
+}
+
+/// Rotational inertia of a polygon around its local origin.
+fn polygon_rotational_inertia(mass: f32, verticies: &[Vec2]) -> f32 {
+    let mut numerator = 0.;
+    let mut denominator = 0.;
+
+    for i in 0..verticies.len() {
+        let a = verticies[i];
+        let b = verticies[(i + 1) % verticies.len()];
+        let cross = a.perp_dot(b);
+
+        numerator += cross * (a.dot(a) + a.dot(b) + b.dot(b));
+        denominator += cross;
+    }
+
+    if denominator == 0. {
+        return 0.;
+    }
+
+    return mass * numerator / (6. * denominator);
+}
+
 pub fn calculate_rotational_inertia(collider: &Collider, flat_body: &FlatBody) -> f32 {
-    match &collider.shape {
+    return shape_rotational_inertia(&collider.shape, flat_body.mass);
+}
+
+/// Area of the shape, segments and chains have none.
+pub fn shape_area(shape: &Shape) -> f32 {
+    match shape {
+        Shape::Box(box_params) => box_params.width * box_params.height,
+        Shape::Circle(circle_params) => f32::consts::PI * circle_params.radius.powi(2),
+        Shape::Polygon(polygon_params) => {
+            let verticies = &polygon_params.verticies;
+            let mut area = 0.;
+            for i in 0..verticies.len() {
+                area += verticies[i].perp_dot(verticies[(i + 1) % verticies.len()]);
+            }
+            (area / 2.).abs()
+        }
+        Shape::Capsule(capsule_params) => {
+            4. * capsule_params.radius * capsule_params.half_length
+                + f32::consts::PI * capsule_params.radius.powi(2)
+        }
+        Shape::Segment(_) | Shape::Chain(_) => 0.,
+        Shape::Compound(compound_params) => compound_params
+            .children
+            .iter()
+            .map(|child| shape_area(&child.shape))
+            .sum(),
+    }
+}
+
+/// Center of mass of the shape in its local space.
+pub fn shape_centroid(shape: &Shape) -> Vec2 {
+    match shape {
+        Shape::Box(_) | Shape::Circle(_) | Shape::Capsule(_) => Vec2::ZERO,
+        Shape::Polygon(polygon_params) => {
+            let verticies = &polygon_params.verticies;
+            let mut centroid = Vec2::ZERO;
+            let mut area = 0.;
+            for i in 0..verticies.len() {
+                let a = verticies[i];
+                let b = verticies[(i + 1) % verticies.len()];
+                let cross = a.perp_dot(b);
+                centroid += (a + b) * cross;
+                area += cross;
+            }
+            if area == 0. {
+                return Vec2::ZERO;
+            }
+            centroid / (3. * area)
+        }
+        Shape::Segment(segment_params) => {
+            (segment_params.verticies[0] + segment_params.verticies[1]) / 2.
+        }
+        Shape::Chain(chain_params) => {
+            let mut centroid = Vec2::ZERO;
+            let mut length = 0.;
+            for w in chain_params.verticies.windows(2) {
+                let segment_length = w[0].distance(w[1]);
+                centroid += (w[0] + w[1]) / 2. * segment_length;
+                length += segment_length;
+            }
+            if length == 0. {
+                return Vec2::ZERO;
+            }
+            centroid / length
+        }
+        Shape::Compound(compound_params) => {
+            let children = &compound_params.children;
+            let total_area = shape_area(shape);
+            let mut centroid = Vec2::ZERO;
+            for child in children {
+                let weight = if total_area > 0. {
+                    shape_area(&child.shape) / total_area
+                } else {
+                    1. / children.len() as f32
+                };
+                centroid += child.local_point(shape_centroid(&child.shape)) * weight;
+            }
+            centroid
+        }
+    }
+}
+
+/// Rotational inertia of the shape with given mass around its local origin.
+pub fn shape_rotational_inertia(shape: &Shape, mass: f32) -> f32 {
+    match shape {
         Shape::Box(box_params) => {
-            return (1. / 12.)
-                * flat_body.mass
-                * (box_params.width.powi(2) + box_params.height.powi(2));
+            return (1. / 12.) * mass * (box_params.width.powi(2) + box_params.height.powi(2));
         }
         Shape::Circle(circle_params) => {
-            return (1. / 2.) * flat_body.mass * circle_params.radius.powi(2);
+            return (1. / 2.) * mass * circle_params.radius.powi(2);
+        }
+        Shape::Polygon(polygon_params) => {
+            return polygon_rotational_inertia(mass, &polygon_params.verticies);
+        }
+        Shape::Capsule(capsule_params) => {
+            let r = capsule_params.radius;
+            let h = capsule_params.half_length;
+
+            // split the mass between the rectangle and the two half circles by area
+            let rect_area = 4. * r * h;
+            let circle_area = f32::consts::PI * r.powi(2);
+            let rect_mass = mass * rect_area / (rect_area + circle_area);
+            let circle_mass = mass - rect_mass;
+
+            let rect_inertia = (1. / 12.) * rect_mass * ((2. * r).powi(2) + (2. * h).powi(2));
+
+            // half circle centroid is 4r/3pi from its flat side, move both halves
+            // from their centroids to the capsule center with the parallel axis theorem
+            let d = 4. * r / (3. * f32::consts::PI);
+            let circle_inertia = circle_mass * (r.powi(2) / 2. - d.powi(2) + (h + d).powi(2));
+
+            return rect_inertia + circle_inertia;
+        }
+        Shape::Segment(segment_params) => {
+            let [a, b] = segment_params.verticies;
+            return segment_rotational_inertia(mass, a, b);
+        }
+        Shape::Chain(chain_params) => {
+            // every segment gets mass proportional to its length
+            let verticies = &chain_params.verticies;
+            let total_length: f32 = verticies.windows(2).map(|w| w[0].distance(w[1])).sum();
+            if total_length == 0. {
+                return 0.;
+            }
+
+            return verticies
+                .windows(2)
+                .map(|w| {
+                    let segment_mass = mass * w[0].distance(w[1]) / total_length;
+                    segment_rotational_inertia(segment_mass, w[0], w[1])
+                })
+                .sum();
+        }
+        Shape::Compound(compound_params) => {
+            // split the mass by area and move every child inertia from its own
+            // centroid to the compound origin with the parallel axis theorem
+            let children = &compound_params.children;
+            let total_area = shape_area(shape);
+
+            return children
+                .iter()
+                .map(|child| {
+                    let child_mass = if total_area > 0. {
+                        mass * shape_area(&child.shape) / total_area
+                    } else {
+                        mass / children.len() as f32
+                    };
+                    let local_centroid = shape_centroid(&child.shape);
+                    let centroid = child.local_point(local_centroid);
+
+                    let inertia_about_centroid = shape_rotational_inertia(&child.shape, child_mass)
+                        - child_mass * local_centroid.length_squared();
+                    inertia_about_centroid + child_mass * centroid.length_squared()
+                })
+                .sum();
         }
     }
 }
@@ -166,3 +542,110 @@ pub fn handle_physics_step(
     // Clear applied forces for next step
     flat_body.force = Vec2::ZERO;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle(width: f32, height: f32) -> Vec<Vec2> {
+        let half = Vec2::new(width, height) / 2.;
+        return vec![
+            Vec2::new(-half.x, -half.y),
+            Vec2::new(half.x, -half.y),
+            Vec2::new(half.x, half.y),
+            Vec2::new(-half.x, half.y),
+        ];
+    }
+
+    #[test]
+    fn polygon_inertia_matches_box() {
+        let box_inertia = shape_rotational_inertia(&Shape::Box(BoxParams::new(60., 20.)), 3.);
+        assert!((box_inertia - 3. * (3600. + 400.) / 12.).abs() < 1e-2);
+
+        for outline in [
+            rectangle(60., 20.),
+            rectangle(60., 20.).into_iter().rev().collect(),
+        ] {
+            let inertia =
+                shape_rotational_inertia(&Shape::Polygon(PolygonParams::new(outline).unwrap()), 3.);
+            assert!(
+                (inertia - box_inertia).abs() < 1e-2,
+                "polygon {inertia} box {box_inertia}"
+            );
+        }
+    }
+
+    #[test]
+    fn polygon_outlines_are_checked() {
+        assert!(is_convex_polygon(&rectangle(60., 20.)));
+        let clockwise: Vec<Vec2> = rectangle(60., 20.).into_iter().rev().collect();
+        assert!(is_convex_polygon(&clockwise));
+        // collinear vertex on the bottom edge
+        assert!(is_convex_polygon(&[
+            Vec2::new(-10., -10.),
+            Vec2::new(0., -10.),
+            Vec2::new(10., -10.),
+            Vec2::new(0., 10.),
+        ]));
+
+        let dented = [
+            Vec2::new(-10., -10.),
+            Vec2::new(10., -10.),
+            Vec2::new(10., 10.),
+            Vec2::new(0., 0.),
+            Vec2::new(-10., 10.),
+        ];
+        assert!(PolygonParams::new(dented.to_vec()).is_none());
+        let star: Vec<Vec2> = (0..5)
+            .map(|i| Vec2::from_angle(f32::consts::TAU * 2. * i as f32 / 5.) * 10.)
+            .collect();
+        assert!(PolygonParams::new(star).is_none());
+        let collinear = [Vec2::ZERO, Vec2::new(10., 0.), Vec2::new(20., 0.)];
+        assert!(PolygonParams::new(collinear.to_vec()).is_none());
+        assert!(PolygonParams::new(vec![Vec2::ZERO, Vec2::X]).is_none());
+    }
+
+    #[test]
+    fn flat_polygon_does_not_turn() {
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        // built directly, `PolygonParams::new` refuses it
+        let flat = PolygonParams {
+            verticies: vec![Vec2::new(-10., 0.), Vec2::ZERO, Vec2::new(10., 0.)],
+        };
+        let body = world
+            .spawn((
+                Transform::default(),
+                FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                Collider::new(Shape::Polygon(flat)),
+            ))
+            .id();
+
+        let flat_body = world.get::<FlatBody>(body).unwrap();
+        assert_eq!(flat_body.inertia(), 0.);
+        assert_eq!(flat_body.inv_inertia(), 0.);
+    }
+
+    #[test]
+    fn compound_inertia_uses_parallel_axis() {
+        // two 20 x 10 boxes 40 apart, the right one standing up, centroid
+        // halfway between them
+        let compound_params = CompoundParams::new(vec![
+            CompoundChild::new(Vec2::ZERO, 0., Shape::Box(BoxParams::new(20., 10.))),
+            CompoundChild::new(
+                Vec2::new(40., 0.),
+                f32::consts::FRAC_PI_2,
+                Shape::Box(BoxParams::new(20., 10.)),
+            ),
+        ]);
+        assert!(compound_params.centroid.distance(Vec2::new(20., 0.)) < 1e-3);
+
+        // each box has mass 1, 500 / 12 around its center and 20^2 more
+        let expected = 2. * (500. / 12. + 400.);
+        let inertia = shape_rotational_inertia(&Shape::Compound(compound_params), 2.);
+        assert!(
+            (inertia - expected).abs() < 1e-2,
+            "inertia {inertia} expected {expected}"
+        );
+    }
+}
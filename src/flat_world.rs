@@ -1,247 +1,271 @@
 use crate::{
+    ccd::continuous_collision,
+    collision_events::CollisionTracker,
+    collision_layers::{CollisionLayers, layers_interact},
     collisions::{
-        Collider, CollisionDetails, ContactPoints, Shape, find_contanct_points, intersect_aabbs,
-        intersect_circle_circle, intersect_circle_polygon, intersects_polygons, separate_bodies,
+        Collider, CollisionDetails, Shape, ShapeContact, apply_ghost_vertices,
+        find_contanct_points, intersect_aabbs, intersect_circle_circle, intersect_circle_polygon,
+        intersects_polygons,
     },
-    flat_body::{FlatBody, FlatBodyType},
-    helpers::{get_global_vertices, nearly_equal_vec, to_vec2},
+    contact_hooks::{ContactHooks, run_contact_hooks},
+    contacts::{ContactCache, pair_key},
+    dynamic_tree::DynamicTree,
+    flat_aabb::FlatAABB,
+    flat_body::{FlatBody, FlatBodyType, handle_physics_step},
+    gjk::{convex_core, gjk, gjk_collide},
+    helpers::{get_global_vertices, to_vec2, vertices_center},
+    one_way_platform::{DropThrough, OneWayPlatform, OneWayPlatformPairs, apply_one_way_platforms},
+    solver::{SolverSettings, solve_contacts},
+    spatial_hash::SpatialHash,
+    sweep_and_prune::SweepAndPrune,
 };
-use bevy::{math::FloatPow, prelude::*};
+use bevy::{ecs::system::SystemParam, prelude::*};
 
-#[derive(Resource, Default)]
+/// Strategy `broad_phase` uses, all of them give the same pairs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BroadPhaseKind {
+    /// Tests every pair, fine for a handful of bodies.
+    BruteForce,
+    /// Uniform grid, good for many bodies of similar size.
+    SpatialHash,
+    /// Bounding volume tree, handles mixed body sizes well.
+    #[default]
+    DynamicTree,
+    /// Sorted AABB ends along the x axis.
+    SweepAndPrune,
+}
+
+#[derive(Resource)]
 pub struct FlatWorld {
     pub gravity: Vec2,
     pub iterations: u32,
+    /// Contact solver and position correction settings.
+    pub solver: SolverSettings,
+    /// Can be changed at any time, the structures of the other kinds are kept.
+    pub broad_phase: BroadPhaseKind,
+    /// Grid used by `spatial_hash_broad_phase`, its cell size can be tuned.
+    pub spatial_hash: SpatialHash,
+    /// Tree used by `dynamic_tree_broad_phase`, kept between steps.
+    pub dynamic_tree: DynamicTree,
+    /// Tree of every body used by `SpatialQuery`, whatever the broad phase.
+    pub query_tree: DynamicTree,
+    /// Sorted endpoints used by `sweep_and_prune_broad_phase`, kept between steps.
+    pub sweep_and_prune: SweepAndPrune,
     pub body_count: usize,
     pub world_step_time_s: u128,
+    /// Contacts resolved in the last sub step, kept by entity pair.
+    pub contacts: ContactCache,
+    /// Also create contacts for bodies which may touch within the sub step.
+    pub speculative_contacts: bool,
+    /// Pairs touching after the last world step, used for collision events.
+    pub collision_tracker: CollisionTracker,
+    /// Pass through decisions of bodies touching one way platforms.
+    pub one_way_platforms: OneWayPlatformPairs,
 }
 
-pub fn resolve_collision_basic(
-    body_a: &FlatBody,
-    body_b: &FlatBody,
-    normal: &Vec2,
-) -> Option<(Vec2, Vec2)> {
-    let relative_velocity = body_b.linear_velocity - body_a.linear_velocity;
-
-    if relative_velocity.dot(*normal) > 0. {
-        return None;
-    }
-
-    let e = body_a.restitution.min(body_b.restitution);
-
-    let mut j = -(1. + e) * relative_velocity.dot(*normal);
-
-    j /= body_a.inv_mass() + body_b.inv_mass();
-
-    let impulse = j * normal;
-    let impulse_a = -impulse * body_a.inv_mass();
-    let impulse_b = impulse * body_b.inv_mass();
-
-    return Some((impulse_a, impulse_b));
-}
-
-pub fn resolve_collision_with_rotation(
-    body_a: &mut FlatBody,
-    transform_a: &Transform,
-    body_b: &mut FlatBody,
-    transform_b: &Transform,
-    contact_points: &ContactPoints,
-    normal: &Vec2,
-) {
-    let e = body_a.restitution.min(body_b.restitution);
-    let mut impulses: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-    let mut ra_list: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-    let mut rb_list: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-
-    for (i, contact_point) in contact_points.iter().enumerate() {
-        let ra = contact_point - to_vec2(&transform_a.translation);
-        let rb = contact_point - to_vec2(&transform_b.translation);
-
-        let ra_perp = Vec2::new(-ra.y, ra.x);
-        let rb_perp = Vec2::new(-rb.y, rb.x);
-
-        let angular_linear_velocity_a = ra_perp * body_a.angular_velocity;
-        let angular_linear_velocity_b = rb_perp * body_b.angular_velocity;
-
-        let relative_velocity = (body_b.linear_velocity + angular_linear_velocity_b)
-            - (body_a.linear_velocity + angular_linear_velocity_a);
-
-        let contact_velocity_magnitude = relative_velocity.dot(*normal);
-
-        if contact_velocity_magnitude > 0. {
-            // bodies are already separating from each other
-            continue;
+impl Default for FlatWorld {
+    fn default() -> Self {
+        FlatWorld {
+            gravity: Vec2::ZERO,
+            iterations: 0,
+            solver: SolverSettings::default(),
+            broad_phase: BroadPhaseKind::default(),
+            spatial_hash: SpatialHash::default(),
+            dynamic_tree: DynamicTree::default(),
+            query_tree: DynamicTree::default(),
+            sweep_and_prune: SweepAndPrune::default(),
+            body_count: 0,
+            world_step_time_s: 0,
+            contacts: ContactCache::default(),
+            speculative_contacts: false,
+            collision_tracker: CollisionTracker::default(),
+            one_way_platforms: OneWayPlatformPairs::default(),
         }
-
-        let ra_perp_dot_n = ra_perp.dot(*normal);
-        let rb_perp_dot_n = rb_perp.dot(*normal);
-
-        let denom = body_a.inv_mass()
-            + body_b.inv_mass()
-            + (ra_perp_dot_n.squared() * body_a.inv_inertia())
-            + (rb_perp_dot_n.squared() * body_b.inv_inertia());
-
-        let mut j = -(1. + e) * contact_velocity_magnitude;
-        j /= denom;
-        j /= contact_points.len() as f32;
-
-        let impulse = j * normal;
-        impulses[i] = impulse;
-        ra_list[i] = ra;
-        rb_list[i] = rb;
     }
+}
 
-    for (i, impulse) in impulses.iter().enumerate() {
-        body_a.linear_velocity += -impulse * body_a.inv_mass();
-        body_a.angular_velocity += -ra_list[i].perp_dot(*impulse) * body_a.inv_inertia();
-        body_b.linear_velocity += impulse * body_b.inv_mass();
-        body_b.angular_velocity += rb_list[i].perp_dot(*impulse) * body_b.inv_inertia();
-    }
+/// What decides which pairs collide and how, read by `sub_step`.
+#[derive(SystemParam)]
+pub struct PairFilters<'w, 's> {
+    layers: Query<'w, 's, &'static CollisionLayers>,
+    platforms: Query<'w, 's, &'static OneWayPlatform>,
+    drop_through: Query<'w, 's, (), With<DropThrough>>,
+    contact_hooks: Res<'w, ContactHooks>,
 }
 
-pub fn resolve_collision_with_rotation_and_friction(
-    body_a: &mut FlatBody,
-    transform_a: &Transform,
-    body_b: &mut FlatBody,
-    transform_b: &Transform,
-    contact_points: &ContactPoints,
-    normal: &Vec2,
+/// One sub step of `world_step`.
+pub fn sub_step(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    filters: &PairFilters,
+    flat_world: &mut FlatWorld,
+    delta_time: f32,
 ) {
-    let e = body_a.restitution.min(body_b.restitution);
-    let sf = (body_a.static_friction() + body_b.static_friction()) * 0.5;
-    let df = (body_a.dynamic_friction() + body_b.dynamic_friction()) * 0.5;
-
-    let mut friction_impulses: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-    let mut impulses: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-    let mut j_list: Vec<f32> = vec![0.0; contact_points.len()];
-    let mut ra_list: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-    let mut rb_list: Vec<Vec2> = vec![Vec2::ZERO; contact_points.len()];
-
-    // bounce and rotation
-    for (i, contact_point) in contact_points.iter().enumerate() {
-        let ra = contact_point - to_vec2(&transform_a.translation);
-        let rb = contact_point - to_vec2(&transform_b.translation);
-
-        let ra_perp = Vec2::new(-ra.y, ra.x);
-        let rb_perp = Vec2::new(-rb.y, rb.x);
-
-        let angular_linear_velocity_a = ra_perp * body_a.angular_velocity;
-        let angular_linear_velocity_b = rb_perp * body_b.angular_velocity;
-
-        let relative_velocity = (body_b.linear_velocity + angular_linear_velocity_b)
-            - (body_a.linear_velocity + angular_linear_velocity_a);
+    let PairFilters {
+        layers,
+        platforms,
+        drop_through,
+        contact_hooks,
+    } = filters;
+    flat_world.contacts.begin_step();
 
-        let contact_velocity_magnitude = relative_velocity.dot(*normal);
-
-        if contact_velocity_magnitude > 0. {
-            // bodies are already separating from each other
+    // physics step
+    let mut ccd_starts = Vec::new();
+    for (entity, mut transform, mut flat_body, _collider) in query.iter_mut() {
+        if let FlatBodyType::Static = flat_body.body_type {
             continue;
         }
+        if flat_body.ccd {
+            ccd_starts.push((entity, *transform));
+        }
 
-        let ra_perp_dot_n = ra_perp.dot(*normal);
-        let rb_perp_dot_n = rb_perp.dot(*normal);
-
-        let denom = body_a.inv_mass()
-            + body_b.inv_mass()
-            + (ra_perp_dot_n.squared() * body_a.inv_inertia())
-            + (rb_perp_dot_n.squared() * body_b.inv_inertia());
-
-        let mut j = -(1. + e) * contact_velocity_magnitude;
-        j /= denom;
-        j /= contact_points.len() as f32;
-
-        let impulse = j * normal;
-        j_list[i] = j;
-        impulses[i] = impulse;
-        ra_list[i] = ra;
-        rb_list[i] = rb;
+        handle_physics_step(
+            &mut transform,
+            &mut flat_body,
+            &flat_world.gravity,
+            delta_time,
+        );
     }
 
-    for (i, impulse) in impulses.iter().enumerate() {
-        body_a.linear_velocity += -impulse * body_a.inv_mass();
-        body_a.angular_velocity += -ra_list[i].perp_dot(*impulse) * body_a.inv_inertia();
-        body_b.linear_velocity += impulse * body_b.inv_mass();
-        body_b.angular_velocity += rb_list[i].perp_dot(*impulse) * body_b.inv_inertia();
+    for (entity, start) in ccd_starts.iter() {
+        continuous_collision(
+            query,
+            layers,
+            platforms,
+            drop_through,
+            *entity,
+            start,
+            delta_time,
+        );
     }
 
-    // friction
-    for (i, contact_point) in contact_points.iter().enumerate() {
-        let ra = contact_point - to_vec2(&transform_a.translation);
-        let rb = contact_point - to_vec2(&transform_b.translation);
-
-        let ra_perp = Vec2::new(-ra.y, ra.x);
-        let rb_perp = Vec2::new(-rb.y, rb.x);
-
-        let angular_linear_velocity_a = ra_perp * body_a.angular_velocity;
-        let angular_linear_velocity_b = rb_perp * body_b.angular_velocity;
-
-        let relative_velocity = (body_b.linear_velocity + angular_linear_velocity_b)
-            - (body_a.linear_velocity + angular_linear_velocity_a);
-
-        let tangent = relative_velocity - relative_velocity.dot(*normal) * *normal;
-
-        if nearly_equal_vec(&tangent, &Vec2::ZERO) {
-            continue;
-        }
+    // Collision step
+    let mut collision_entitties = Vec::new();
+    broad_phase(
+        query,
+        layers,
+        flat_world,
+        &mut collision_entitties,
+        delta_time,
+    );
+    // collision resolve
+    let speculative_time = if flat_world.speculative_contacts {
+        delta_time
+    } else {
+        0.
+    };
+    narrow_phase(
+        query,
+        &collision_entitties,
+        &mut flat_world.contacts,
+        speculative_time,
+    );
+    apply_one_way_platforms(
+        query,
+        platforms,
+        drop_through,
+        &mut flat_world.one_way_platforms,
+        &mut flat_world.contacts,
+    );
+    run_contact_hooks(contact_hooks, &mut flat_world.contacts);
+    let solver_settings = flat_world.solver;
+    solve_contacts(
+        query,
+        &mut flat_world.contacts,
+        &solver_settings,
+        delta_time,
+    );
+}
 
-        let tangent = tangent.normalize();
+/// Runs `steps` sub steps on the bodies of `world` like `world_step` does.
+#[cfg(test)]
+pub fn run_sub_steps(world: &mut World, steps: usize, delta_time: f32) {
+    use bevy::ecs::system::RunSystemOnce;
 
-        let ra_perp_dot_t = ra_perp.dot(tangent);
-        let rb_perp_dot_t = rb_perp.dot(tangent);
+    world.init_resource::<FlatWorld>();
+    world.init_resource::<ContactHooks>();
+    world
+        .run_system_once(
+            move |mut query: Query<(Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+                  filters: PairFilters,
+                  mut flat_world: ResMut<FlatWorld>| {
+                for _step in 0..steps {
+                    sub_step(&mut query, &filters, &mut flat_world, delta_time);
+                }
+            },
+        )
+        .unwrap();
+}
 
-        let denom = body_a.inv_mass()
-            + body_b.inv_mass()
-            + (ra_perp_dot_t.squared() * body_a.inv_inertia())
-            + (rb_perp_dot_t.squared() * body_b.inv_inertia());
+pub fn collide(
+    entity_a: (&Transform, &Shape),
+    entity_b: (&Transform, &Shape),
+) -> Option<CollisionDetails> {
+    let collision = collide_shapes(entity_a, entity_b)?;
+    return smooth_segment_collision(entity_a, entity_b, collision, 0.);
+}
 
-        let mut jt = -relative_velocity.dot(tangent);
-        jt /= denom;
-        jt /= contact_points.len() as f32;
-        let j = j_list[i];
+/// Smooths out collisions with chain segments, see `apply_ghost_vertices`.
+fn smooth_segment_collision(
+    entity_a: (&Transform, &Shape),
+    entity_b: (&Transform, &Shape),
+    collision: CollisionDetails,
+    speculative_distance: f32,
+) -> Option<CollisionDetails> {
+    let (pos_a, shape_a) = entity_a;
+    let (pos_b, shape_b) = entity_b;
 
-        if jt.abs() <= j * sf {
-            let impulse_friction = jt * tangent;
-            friction_impulses[i] = impulse_friction;
-        } else {
-            let impulse_friction = -j * tangent * df;
-            friction_impulses[i] = impulse_friction;
-        }
+    if let Shape::Segment(segment_params) = shape_a {
+        return apply_ghost_vertices(
+            segment_params,
+            pos_a,
+            shape_b,
+            pos_b,
+            collision,
+            speculative_distance,
+        );
+    } else if let Shape::Segment(segment_params) = shape_b {
+        let mut collision = collision;
+        collision.collision_normal *= -1.;
+        let mut collision = apply_ghost_vertices(
+            segment_params,
+            pos_b,
+            shape_a,
+            pos_a,
+            collision,
+            speculative_distance,
+        )?;
+        collision.collision_normal *= -1.;
+        return Some(collision);
     }
 
-    for (i, impulse) in friction_impulses.iter().enumerate() {
-        body_a.linear_velocity += -impulse * body_a.inv_mass();
-        body_a.angular_velocity += -ra_list[i].perp_dot(*impulse) * body_a.inv_inertia();
-        body_b.linear_velocity += impulse * body_b.inv_mass();
-        body_b.angular_velocity += rb_list[i].perp_dot(*impulse) * body_b.inv_inertia();
-    }
+    return Some(collision);
 }
 
-pub fn collide(
-    entity_a: (&Transform, &Collider),
-    entity_b: (&Transform, &Collider),
+fn collide_shapes(
+    entity_a: (&Transform, &Shape),
+    entity_b: (&Transform, &Shape),
 ) -> Option<CollisionDetails> {
-    let (pos_a, collider_a) = entity_a;
-    let (pos_b, collider_b) = entity_b;
+    let (pos_a, shape_a) = entity_a;
+    let (pos_b, shape_b) = entity_b;
 
-    if let Shape::Box(box_params_a) = &collider_a.shape {
-        if let Shape::Box(box_params_b) = &collider_b.shape {
-            let vertices_a = get_global_vertices(&pos_a, &box_params_a.verticies);
-            let vertices_b = get_global_vertices(&pos_b, &box_params_b.verticies);
-
-            return intersects_polygons(
-                &vertices_a,
-                &to_vec2(&pos_a.translation),
-                &vertices_b,
-                &to_vec2(&pos_b.translation),
+    // SAT for circles and polygons, GJK with EPA for any other convex pair
+    match (shape_a, shape_b) {
+        (Shape::Circle(circle_params_a), Shape::Circle(circle_params_b)) => {
+            return intersect_circle_circle(
+                to_vec2(&pos_a.translation),
+                circle_params_a.radius,
+                to_vec2(&pos_b.translation),
+                circle_params_b.radius,
             );
-        } else if let Shape::Circle(circle_params_b) = &collider_b.shape {
-            let vertices_a = get_global_vertices(&pos_a, &box_params_a.verticies);
+        }
+        (shape_a, Shape::Circle(circle_params_b)) if shape_a.verticies().is_some() => {
+            let vertices_a = get_global_vertices(&pos_a, shape_a.verticies()?);
 
             let mut collision = intersect_circle_polygon(
                 &to_vec2(&pos_b.translation),
                 circle_params_b.radius,
                 &vertices_a,
-                &to_vec2(&pos_a.translation),
+                &vertices_center(&vertices_a),
             );
 
             if let Some(coll) = &mut collision {
@@ -249,47 +273,146 @@ pub fn collide(
             }
             return collision;
         }
-    } else if let Shape::Circle(circle_params_a) = &collider_a.shape {
-        if let Shape::Box(box_params_b) = &collider_b.shape {
-            let vertices_a = get_global_vertices(&pos_b, &box_params_b.verticies);
+        (Shape::Circle(circle_params_a), shape_b) if shape_b.verticies().is_some() => {
+            let vertices_b = get_global_vertices(&pos_b, shape_b.verticies()?);
 
             return intersect_circle_polygon(
                 &to_vec2(&pos_a.translation),
                 circle_params_a.radius,
-                &vertices_a,
-                &to_vec2(&pos_b.translation),
+                &vertices_b,
+                &vertices_center(&vertices_b),
             );
-        } else if let Shape::Circle(circle_params_b) = &collider_b.shape {
-            return intersect_circle_circle(
-                to_vec2(&pos_a.translation),
-                circle_params_a.radius,
-                to_vec2(&pos_b.translation),
-                circle_params_b.radius,
+        }
+        (shape_a, shape_b) if shape_a.verticies().is_some() && shape_b.verticies().is_some() => {
+            let vertices_a = get_global_vertices(&pos_a, shape_a.verticies()?);
+            let vertices_b = get_global_vertices(&pos_b, shape_b.verticies()?);
+
+            return intersects_polygons(
+                &vertices_a,
+                &vertices_center(&vertices_a),
+                &vertices_b,
+                &vertices_center(&vertices_b),
             );
         }
+        (shape_a, shape_b) => {
+            return gjk_collide(&convex_core(shape_a, pos_a)?, &convex_core(shape_b, pos_b)?);
+        }
+    }
+}
+
+/// AABB of the body grown by its motion over `speculative_time`.
+fn body_aabb(
+    transform: &Mut<Transform>,
+    flat_body: &FlatBody,
+    collider: &mut Mut<Collider>,
+    speculative_time: f32,
+) -> FlatAABB {
+    let collider_changed = collider.is_changed();
+    let collider = collider.bypass_change_detection();
+    if transform.is_changed() || collider_changed {
+        collider.update_aabb();
     }
+    let aabb = *collider.get_aabb(transform);
+    let rotation_distance = flat_body.angular_velocity.abs()
+        * speculative_time
+        * max_extent(&aabb, to_vec2(&transform.translation));
+    return aabb
+        .swept(flat_body.linear_velocity * speculative_time)
+        .expanded(rotation_distance);
+}
+
+/// Distance from `origin` to the farthest corner of `aabb`.
+fn max_extent(aabb: &FlatAABB, origin: Vec2) -> f32 {
+    let far_corner = (aabb.min - origin).abs().max((aabb.max - origin).abs());
+    return far_corner.length();
+}
 
-    return None;
+/// Fastest any point of the collider moves because the body turns.
+fn turning_speed(transform: &Transform, flat_body: &FlatBody, collider: &Collider) -> f32 {
+    let aabb = collider.shape.get_aabb(transform);
+    return flat_body.angular_velocity.abs() * max_extent(&aabb, to_vec2(&transform.translation));
 }
 
+/// Static bodies are not paired with each other, unless one is a sensor.
+fn pairs_as_static(flat_body: &FlatBody, collider: &Collider) -> bool {
+    return matches!(flat_body.body_type, FlatBodyType::Static) && !collider.sensor;
+}
+
+/// Runs the broad phase selected in `flat_world.broad_phase`.
 pub fn broad_phase(
     query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
+    flat_world: &mut FlatWorld,
+    collision_entitties: &mut Vec<(Entity, Entity)>,
+    delta_time: f32,
+) {
+    // AABBs are grown by the motion of the step so speculative pairs are found
+    let speculative_time = if flat_world.speculative_contacts {
+        delta_time
+    } else {
+        0.
+    };
+
+    match flat_world.broad_phase {
+        BroadPhaseKind::BruteForce => {
+            brute_force_broad_phase(query, layers, collision_entitties, speculative_time)
+        }
+        BroadPhaseKind::SpatialHash => spatial_hash_broad_phase(
+            query,
+            layers,
+            &mut flat_world.spatial_hash,
+            collision_entitties,
+            speculative_time,
+        ),
+        BroadPhaseKind::DynamicTree => dynamic_tree_broad_phase(
+            query,
+            layers,
+            &mut flat_world.dynamic_tree,
+            collision_entitties,
+            speculative_time,
+        ),
+        BroadPhaseKind::SweepAndPrune => sweep_and_prune_broad_phase(
+            query,
+            layers,
+            &mut flat_world.sweep_and_prune,
+            collision_entitties,
+            speculative_time,
+        ),
+    }
+}
+
+pub fn brute_force_broad_phase(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
     collision_entitties: &mut Vec<(Entity, Entity)>,
+    speculative_time: f32,
 ) {
     let mut combinations = query.iter_combinations_mut();
     while let Some([a1, a2]) = combinations.fetch_next() {
         let (entity_a, transform_a, flat_body_a, mut collider_a) = a1;
         let (entity_b, transform_b, flat_body_b, mut collider_b) = a2;
 
-        if let FlatBodyType::Static = flat_body_a.body_type
-            && let FlatBodyType::Static = flat_body_b.body_type
+        if pairs_as_static(&flat_body_a, &collider_a) && pairs_as_static(&flat_body_b, &collider_b)
         {
             continue;
         }
+        if !layers_interact(layers, entity_a, entity_b) {
+            continue;
+        }
 
         if !intersect_aabbs(
-            &collider_a.get_aabb(&transform_a),
-            &collider_b.get_aabb(&transform_b),
+            &body_aabb(
+                &transform_a,
+                &flat_body_a,
+                &mut collider_a,
+                speculative_time,
+            ),
+            &body_aabb(
+                &transform_b,
+                &flat_body_b,
+                &mut collider_b,
+                speculative_time,
+            ),
         ) {
             continue;
         }
@@ -298,39 +421,762 @@ pub fn broad_phase(
     }
 }
 
+/// Same pairs as `brute_force_broad_phase`, but only bodies sharing a grid cell are tested.
+pub fn spatial_hash_broad_phase(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
+    spatial_hash: &mut SpatialHash,
+    collision_entitties: &mut Vec<(Entity, Entity)>,
+    speculative_time: f32,
+) {
+    let mut entities = Vec::new();
+    let mut statics = Vec::new();
+    let mut aabbs = Vec::new();
+    for (entity, transform, flat_body, mut collider) in query.iter_mut() {
+        entities.push(entity);
+        statics.push(pairs_as_static(&flat_body, &collider));
+        aabbs.push(body_aabb(
+            &transform,
+            &flat_body,
+            &mut collider,
+            speculative_time,
+        ));
+    }
+
+    for (a, b) in spatial_hash.candidate_pairs(&aabbs) {
+        if statics[a] && statics[b] {
+            continue;
+        }
+        if !layers_interact(layers, entities[a], entities[b]) {
+            continue;
+        }
+        if !intersect_aabbs(&aabbs[a], &aabbs[b]) {
+            continue;
+        }
+        collision_entitties.push((entities[a], entities[b]));
+    }
+}
+
+/// Same pairs as `brute_force_broad_phase`, found through the bounding volume tree.
+pub fn dynamic_tree_broad_phase(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
+    dynamic_tree: &mut DynamicTree,
+    collision_entitties: &mut Vec<(Entity, Entity)>,
+    speculative_time: f32,
+) {
+    for (entity, transform, flat_body, mut collider) in query.iter_mut() {
+        if transform.is_changed() || collider.is_changed() {
+            collider.bypass_change_detection().update_aabb();
+        }
+        if !collider.needs_aabb_update() && dynamic_tree.contains(entity) {
+            continue;
+        }
+        let is_static = pairs_as_static(&flat_body, &collider);
+        let aabb = body_aabb(&transform, &flat_body, &mut collider, speculative_time);
+        dynamic_tree.update(entity, aabb, is_static);
+    }
+    // despawned bodies
+    dynamic_tree.retain(|entity| query.contains(entity));
+
+    collision_entitties.extend(
+        dynamic_tree.find_pairs(|entity_a, entity_b| layers_interact(layers, entity_a, entity_b)),
+    );
+}
+
+/// Moves the leaves of `query_tree` after a step and drops despawned bodies.
+pub fn update_query_tree(
+    query: &Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    query_tree: &mut DynamicTree,
+) {
+    for (entity, transform, flat_body, collider) in query.iter() {
+        query_tree.update(
+            entity,
+            collider.shape.get_aabb(transform),
+            pairs_as_static(flat_body, collider),
+        );
+    }
+    query_tree.retain(|entity| query.contains(entity));
+}
+
+/// Puts new bodies into `query_tree`.
+pub fn insert_into_query_tree(
+    event: On<Add, (FlatBody, Collider)>,
+    query: Query<(&FlatBody, &Collider, &Transform)>,
+    mut flat_world: ResMut<FlatWorld>,
+) {
+    let Ok((flat_body, collider, transform)) = query.get(event.entity) else {
+        return;
+    };
+    flat_world.query_tree.update(
+        event.entity,
+        collider.shape.get_aabb(transform),
+        pairs_as_static(flat_body, collider),
+    );
+}
+
+/// Bodies moved or reshaped since a system last ran.
+type ColliderChanged = Or<(Changed<Transform>, Changed<Collider>)>;
+
+/// Moves the leaves of bodies moved or reshaped outside of `world_step`.
+pub fn refresh_query_tree(
+    query: Query<(Entity, &FlatBody, &Collider, &Transform), ColliderChanged>,
+    mut flat_world: ResMut<FlatWorld>,
+) {
+    for (entity, flat_body, collider, transform) in query.iter() {
+        flat_world.query_tree.update(
+            entity,
+            collider.shape.get_aabb(transform),
+            pairs_as_static(flat_body, collider),
+        );
+    }
+}
+
+/// Same pairs as `brute_force_broad_phase`, found by sweeping the sorted AABB ends.
+pub fn sweep_and_prune_broad_phase(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    layers: &Query<'_, '_, &CollisionLayers>,
+    sweep_and_prune: &mut SweepAndPrune,
+    collision_entitties: &mut Vec<(Entity, Entity)>,
+    speculative_time: f32,
+) {
+    for (entity, transform, flat_body, mut collider) in query.iter_mut() {
+        let is_static = pairs_as_static(&flat_body, &collider);
+        let aabb = body_aabb(&transform, &flat_body, &mut collider, speculative_time);
+        sweep_and_prune.update(entity, aabb, is_static);
+    }
+    // despawned bodies
+    sweep_and_prune.retain(|entity| query.contains(entity));
+
+    collision_entitties.extend(
+        sweep_and_prune
+            .find_pairs(|entity_a, entity_b| layers_interact(layers, entity_a, entity_b)),
+    );
+}
+
 pub fn narrow_phase(
     query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
     collision_entitties: &Vec<(Entity, Entity)>,
+    contacts: &mut ContactCache,
+    speculative_time: f32,
 ) {
     for (entity_a, entity_b) in collision_entitties.iter() {
+        // same order every step so the contact normal and feature ids stay comparable
+        let (entity_a, entity_b) = &pair_key(*entity_a, *entity_b);
         let [
-            (_entity_a, mut transform_a, mut flat_body_a, collider_a),
-            (_entity_b, mut transform_b, mut flat_body_b, collider_b),
+            (_entity_a, transform_a, flat_body_a, collider_a),
+            (_entity_b, transform_b, flat_body_b, collider_b),
         ] = match query.get_many_mut([*entity_a, *entity_b]) {
             Ok(val) => val,
             Err(_) => continue,
         };
 
-        let collision = collide((&transform_a, &collider_a), (&transform_b, &collider_b));
-        if let Some(collision_info) = collision {
-            separate_bodies(
-                &mut transform_a,
-                &mut transform_b,
-                &flat_body_a,
-                &flat_body_b,
-                &collision_info,
+        let sub_shapes_a = collider_a.shape.sub_shapes();
+        let sub_shapes_b = collider_b.shape.sub_shapes();
+        let multi_part = sub_shapes_a.len() > 1 || sub_shapes_b.len() > 1;
+        let is_sensor = collider_a.sensor || collider_b.sensor;
+        // gap the bodies can close in this step by moving or by turning, sensors
+        // only report real overlaps
+        let speculative_distance = if speculative_time > 0. && !is_sensor {
+            let closing_speed = (flat_body_b.linear_velocity - flat_body_a.linear_velocity)
+                .length()
+                + turning_speed(&transform_a, &flat_body_a, &collider_a)
+                + turning_speed(&transform_b, &flat_body_b, &collider_b);
+            closing_speed * speculative_time
+        } else {
+            0.
+        };
+
+        for (index_a, local_a, shape_a) in sub_shapes_a.iter() {
+            for (index_b, local_b, shape_b) in sub_shapes_b.iter() {
+                let sub_transform_a = transform_a.mul_transform(*local_a);
+                let sub_transform_b = transform_b.mul_transform(*local_b);
+
+                if multi_part
+                    && !intersect_aabbs(
+                        &shape_a
+                            .get_aabb(&sub_transform_a)
+                            .expanded(speculative_distance),
+                        &shape_b.get_aabb(&sub_transform_b),
+                    )
+                {
+                    continue;
+                }
+
+                let collision = collide((&sub_transform_a, shape_a), (&sub_transform_b, shape_b))
+                    .or_else(|| {
+                        speculative_collision(
+                            (&sub_transform_a, shape_a),
+                            (&sub_transform_b, shape_b),
+                            speculative_distance,
+                        )
+                    });
+                if let Some(collision_info) = collision {
+                    let contact_points = if is_sensor {
+                        Vec::new()
+                    } else {
+                        find_contanct_points(
+                            &sub_transform_a,
+                            shape_a,
+                            &sub_transform_b,
+                            shape_b,
+                            &collision_info,
+                        )
+                    };
+
+                    contacts.insert(ShapeContact {
+                        entity_a: *entity_a,
+                        entity_b: *entity_b,
+                        sub_shape_a: *index_a,
+                        sub_shape_b: *index_b,
+                        collision_normal: collision_info.collision_normal,
+                        penetration_depth: collision_info.penetration_depth,
+                        contact_points,
+                        is_sensor,
+                        enabled: true,
+                        static_friction: (flat_body_a.static_friction()
+                            + flat_body_b.static_friction())
+                            * 0.5,
+                        dynamic_friction: (flat_body_a.dynamic_friction()
+                            + flat_body_b.dynamic_friction())
+                            * 0.5,
+                        restitution: flat_body_a.restitution.min(flat_body_b.restitution),
+                        tangent_speed: 0.,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Normal and negative depth of separated shapes closer than `max_distance`.
+fn speculative_collision(
+    entity_a: (&Transform, &Shape),
+    entity_b: (&Transform, &Shape),
+    max_distance: f32,
+) -> Option<CollisionDetails> {
+    if max_distance <= 0. {
+        return None;
+    }
+    let (pos_a, shape_a) = entity_a;
+    let (pos_b, shape_b) = entity_b;
+
+    let core_a = convex_core(shape_a, pos_a)?;
+    let core_b = convex_core(shape_b, pos_b)?;
+    let output = gjk(&core_a, &core_b);
+    if output.overlapping {
+        return None;
+    }
+
+    let separation = output.distance - core_a.radius - core_b.radius;
+    let normal = (output.point_b - output.point_a).normalize_or_zero();
+    // exactly touching shapes are not found by `collide`, they are kept here
+    if separation > max_distance || normal == Vec2::ZERO {
+        return None;
+    }
+    let collision = CollisionDetails {
+        penetration_depth: -separation,
+        collision_normal: normal,
+    };
+    // same seam handling as `collide`, or chains get vertex normals again
+    return smooth_segment_collision(entity_a, entity_b, collision, max_distance);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::flat_body::{
+        BoxParams, CapsuleParams, ChainParams, CircleParams, CompoundChild, CompoundParams,
+        FlatBodyType, on_flat_body_added,
+    };
+
+    #[test]
+    fn box_against_offset_chain_segment() {
+        // vertical segment at x = 100, left of the chain origin at x = 200
+        let chain = ChainParams::new(vec![Vec2::new(-100., -100.), Vec2::new(-100., 100.)]);
+        let chain_transform = Transform::from_xyz(200., 0., 0.);
+        let segment = Shape::Segment(chain.segment(0));
+        let box_shape = Shape::Box(BoxParams::new(50., 50.));
+        let box_transform = Transform::from_xyz(120., 0., 0.);
+
+        let collision = collide((&box_transform, &box_shape), (&chain_transform, &segment))
+            .expect("box overlaps the segment");
+        assert!(
+            collision.collision_normal.distance(Vec2::NEG_X) < 1e-3,
+            "normal {}",
+            collision.collision_normal
+        );
+        assert!((collision.penetration_depth - 5.).abs() < 1e-3);
+
+        let collision = collide((&chain_transform, &segment), (&box_transform, &box_shape))
+            .expect("segment overlaps the box");
+        assert!(
+            collision.collision_normal.distance(Vec2::X) < 1e-3,
+            "normal {}",
+            collision.collision_normal
+        );
+    }
+
+    /// Final velocity and contact normals of a box sliding over a seam.
+    fn slide_over_seam(ground: Vec<(Transform, Shape)>) -> (f32, Vec<Vec2>) {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld {
+            gravity: Vec2::new(0., -300.),
+            ..Default::default()
+        });
+        let mut contact_hooks = ContactHooks::default();
+        contact_hooks.register(|contact: &mut ShapeContact| {
+            contact.static_friction = 0.;
+            contact.dynamic_friction = 0.;
+        });
+        world.insert_resource(contact_hooks);
+        world.add_observer(on_flat_body_added);
+        for (transform, shape) in ground {
+            world.spawn((
+                transform,
+                FlatBody::new(1., FlatBodyType::Static, 0.),
+                Collider::new(shape),
+            ));
+        }
+        let mut sliding = FlatBody::new(1., FlatBodyType::Dynamic, 0.);
+        sliding.linear_velocity = Vec2::new(100., 0.);
+        let body = world
+            .spawn((
+                Transform::from_xyz(-50., 20., 0.),
+                sliding,
+                Collider::new(Shape::Box(BoxParams::new(40., 40.))),
+            ))
+            .id();
+
+        let mut normals = Vec::new();
+        for _step in 0..384 {
+            run_sub_steps(&mut world, 1, 1. / 384.);
+            let contacts = &world.resource::<FlatWorld>().contacts;
+            normals.extend(contacts.iter().map(|contact| contact.collision_normal));
+        }
+        let velocity = world.get::<FlatBody>(body).unwrap().linear_velocity;
+        return (velocity.x, normals);
+    }
+
+    #[test]
+    fn box_slides_over_chain_seams() {
+        let chain = ChainParams::new(vec![
+            Vec2::new(-400., 0.),
+            Vec2::new(-40., 0.),
+            Vec2::ZERO,
+            Vec2::new(400., 0.),
+        ]);
+        let (velocity, normals) =
+            slide_over_seam(vec![(Transform::default(), Shape::Chain(chain))]);
+        assert!(!normals.is_empty());
+        for normal in normals.iter() {
+            assert!(normal.x.abs() < 1e-3, "normal {normal}");
+        }
+        assert!((velocity - 100.).abs() < 0.5, "velocity {velocity}");
+
+        // touching boxes have real corners at the seam and the box catches on them
+        let ground_box = Shape::Box(BoxParams::new(400., 100.));
+        let (velocity, normals) = slide_over_seam(vec![
+            (Transform::from_xyz(-200., -50., 0.), ground_box.clone()),
+            (Transform::from_xyz(200., -50., 0.), ground_box),
+        ]);
+        assert!(normals.iter().any(|normal| normal.x.abs() > 0.5));
+        assert!(velocity < 90., "velocity {velocity}");
+    }
+
+    #[test]
+    fn contact_reports_compound_child() {
+        let mut world = World::new();
+        let compound = world
+            .spawn((
+                Transform::default(),
+                FlatBody::new(1., FlatBodyType::Static, 0.),
+                Collider::new(Shape::Compound(CompoundParams {
+                    children: vec![
+                        CompoundChild::new(
+                            Vec2::new(-100., 0.),
+                            0.,
+                            Shape::Box(BoxParams::new(50., 50.)),
+                        ),
+                        CompoundChild::new(
+                            Vec2::new(100., 0.),
+                            0.,
+                            Shape::Box(BoxParams::new(50., 50.)),
+                        ),
+                    ],
+                    centroid: Vec2::ZERO,
+                })),
+            ))
+            .id();
+        // sunk into the top of the right box only
+        world.spawn((
+            Transform::from_xyz(100., 45., 0.),
+            FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+            Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+        ));
+
+        run_sub_steps(&mut world, 1, 1. / 60.);
+
+        let contacts: Vec<&ShapeContact> = world.resource::<FlatWorld>().contacts.iter().collect();
+        assert_eq!(contacts.len(), 1);
+        let contact = contacts[0];
+        let (compound_sub_shape, box_sub_shape) = if contact.entity_a == compound {
+            (contact.sub_shape_a, contact.sub_shape_b)
+        } else {
+            (contact.sub_shape_b, contact.sub_shape_a)
+        };
+        assert_eq!(compound_sub_shape, 1);
+        assert_eq!(box_sub_shape, 0);
+    }
+
+    fn assert_finite_contact(
+        shape_a: &Shape,
+        transform_a: &Transform,
+        shape_b: &Shape,
+        transform_b: &Transform,
+    ) {
+        let collision =
+            collide((transform_a, shape_a), (transform_b, shape_b)).expect("shapes overlap");
+        assert!(
+            collision.collision_normal.is_normalized(),
+            "normal {}",
+            collision.collision_normal
+        );
+        assert!(collision.penetration_depth > 0.);
+        let contact_points =
+            find_contanct_points(transform_a, shape_a, transform_b, shape_b, &collision);
+        assert!(!contact_points.is_empty());
+        for point in contact_points.iter() {
+            assert!(point.position.is_finite(), "point {}", point.position);
+        }
+    }
+
+    #[test]
+    fn concentric_and_crossed_shapes() {
+        let circle = Shape::Circle(CircleParams::new(20.));
+        let center = Transform::from_xyz(10., 10., 0.);
+        assert_finite_contact(&circle, &center, &circle, &center);
+
+        // inner segments cross at the origin
+        let capsule = Shape::Capsule(CapsuleParams::new(10., 100.));
+        let crossed = Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        assert_finite_contact(&capsule, &Transform::IDENTITY, &capsule, &crossed);
+        assert_finite_contact(
+            &capsule,
+            &Transform::IDENTITY,
+            &capsule,
+            &Transform::IDENTITY,
+        );
+        assert_finite_contact(
+            &capsule,
+            &Transform::IDENTITY,
+            &circle,
+            &Transform::IDENTITY,
+        );
+    }
+
+    #[test]
+    fn static_sensor_overlaps_static_box() {
+        let mut world = World::new();
+        let sensor = world
+            .spawn((
+                Transform::default(),
+                FlatBody::new(1., FlatBodyType::Static, 0.),
+                Collider::sensor(Shape::Box(BoxParams::new(100., 100.))),
+            ))
+            .id();
+        let wall = world
+            .spawn((
+                Transform::from_xyz(60., 0., 0.),
+                FlatBody::new(1., FlatBodyType::Static, 0.),
+                Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+            ))
+            .id();
+
+        run_sub_steps(&mut world, 1, 1. / 60.);
+
+        let flat_world = world.resource::<FlatWorld>();
+        let overlaps: Vec<_> = flat_world.contacts.sensor_overlaps().collect();
+        assert_eq!(overlaps, [pair_key(sensor, wall)]);
+    }
+
+    /// Drops a box for half a second, through a sensor when `with_sensor`.
+    fn drop_box(with_sensor: bool) -> (World, Entity) {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld {
+            gravity: Vec2::new(0., -300.),
+            ..Default::default()
+        });
+        world.add_observer(on_flat_body_added);
+        if with_sensor {
+            world.spawn((
+                Transform::from_xyz(0., -50., 0.),
+                FlatBody::new(1., FlatBodyType::Static, 0.),
+                Collider::sensor(Shape::Box(BoxParams::new(200., 100.))),
+            ));
+        }
+        let mut flat_body = FlatBody::new(1., FlatBodyType::Dynamic, 0.);
+        flat_body.angular_velocity = 1.;
+        let body = world
+            .spawn((
+                Transform::default(),
+                flat_body,
+                Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+            ))
+            .id();
+        return (world, body);
+    }
+
+    #[test]
+    fn dynamic_body_falls_through_sensor() {
+        let (mut free_world, free_body) = drop_box(false);
+        let (mut world, body) = drop_box(true);
+        for _step in 0..30 {
+            run_sub_steps(&mut free_world, 1, 1. / 60.);
+            run_sub_steps(&mut world, 1, 1. / 60.);
+
+            let contacts = &world.resource::<FlatWorld>().contacts;
+            assert_eq!(contacts.sensor_overlaps().count(), 1);
+            for contact in contacts.iter() {
+                assert!(contact.is_sensor);
+                assert!(contact.contact_points.is_empty());
+            }
+            assert_eq!(
+                world.get::<Transform>(body),
+                free_world.get::<Transform>(free_body)
             );
-            let contact_points =
-                find_contanct_points(&transform_a, &collider_a, &transform_b, &collider_b);
+            let flat_body = world.get::<FlatBody>(body).unwrap();
+            let free_flat_body = free_world.get::<FlatBody>(free_body).unwrap();
+            assert_eq!(flat_body.linear_velocity, free_flat_body.linear_velocity);
+            assert_eq!(flat_body.angular_velocity, free_flat_body.angular_velocity);
+        }
+    }
 
-            resolve_collision_with_rotation_and_friction(
-                &mut flat_body_a,
-                &transform_a,
-                &mut flat_body_b,
-                &transform_b,
-                &contact_points,
-                &collision_info.collision_normal,
+    #[derive(Resource, Default)]
+    struct FoundPairs(Vec<(Entity, Entity)>);
+
+    #[test]
+    fn cached_aabb_follows_collider_changes() {
+        let mut world = World::new();
+        world.init_resource::<FoundPairs>();
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(100., 100.))),
+        ));
+        let body = world
+            .spawn((
+                Transform::from_xyz(0., 100., 0.),
+                FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+            ))
+            .id();
+
+        // registered systems keep their change ticks between runs
+        let broad_phases = world.register_system(
+            |mut query: Query<(Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+             layers: Query<&CollisionLayers>,
+             mut dynamic_tree: Local<DynamicTree>,
+             mut found: ResMut<FoundPairs>| {
+                let mut pairs = Vec::new();
+                brute_force_broad_phase(&mut query, &layers, &mut pairs, 0.);
+                let mut tree_pairs = Vec::new();
+                dynamic_tree_broad_phase(
+                    &mut query,
+                    &layers,
+                    &mut dynamic_tree,
+                    &mut tree_pairs,
+                    0.,
+                );
+                assert_eq!(pairs.len(), tree_pairs.len());
+                found.0 = pairs;
+            },
+        );
+        world.run_system(broad_phases).unwrap();
+        world.run_system(broad_phases).unwrap();
+        assert!(world.resource::<FoundPairs>().0.is_empty());
+
+        // grows down into the static box without moving
+        world.get_mut::<Collider>(body).unwrap().shape = Shape::Box(BoxParams::new(50., 120.));
+        world.run_system(broad_phases).unwrap();
+        assert_eq!(world.resource::<FoundPairs>().0.len(), 1);
+    }
+
+    #[test]
+    fn speculative_contacts_cover_turning() {
+        let mut world = World::new();
+        // the end of the rod turns about 5 up in the step, the box is 3 above it
+        let mut rod = FlatBody::new(1., FlatBodyType::Dynamic, 0.);
+        rod.angular_velocity = 3.;
+        world.spawn((
+            Transform::default(),
+            rod,
+            Collider::new(Shape::Box(BoxParams::new(200., 10.))),
+        ));
+        world.spawn((
+            Transform::from_xyz(90., 18., 0.),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(20., 20.))),
+        ));
+
+        let contact_count = world
+            .run_system_once(
+                |mut query: Query<(Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+                 layers: Query<&CollisionLayers>| {
+                    let speculative_time = 1. / 60.;
+                    let mut pairs = Vec::new();
+                    brute_force_broad_phase(&mut query, &layers, &mut pairs, speculative_time);
+                    let mut contacts = ContactCache::default();
+                    narrow_phase(&mut query, &pairs, &mut contacts, speculative_time);
+                    return contacts.iter().count();
+                },
             )
+            .unwrap();
+        assert_eq!(contact_count, 1);
+    }
+
+    /// Small deterministic generator so failures can be reproduced.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (self.0 >> 40) as f32 / (1u64 << 24) as f32;
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            return min + (max - min) * self.next();
+        }
+    }
+
+    struct Body {
+        entity: Entity,
+        aabb: FlatAABB,
+        is_static: bool,
+    }
+
+    fn filter(a: Entity, b: Entity) -> bool {
+        return !(a.index_u32() + b.index_u32()).is_multiple_of(7);
+    }
+
+    fn sorted_pair(a: Entity, b: Entity) -> (Entity, Entity) {
+        return (a.min(b), a.max(b));
+    }
+
+    fn brute_force_pairs(bodies: &[Body]) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for (i, a) in bodies.iter().enumerate() {
+            for b in bodies[i + 1..].iter() {
+                if (a.is_static && b.is_static) || !filter(a.entity, b.entity) {
+                    continue;
+                }
+                if intersect_aabbs(&a.aabb, &b.aabb) {
+                    pairs.push(sorted_pair(a.entity, b.entity));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    fn spatial_hash_pairs(
+        spatial_hash: &mut SpatialHash,
+        bodies: &[Body],
+    ) -> Vec<(Entity, Entity)> {
+        let aabbs: Vec<FlatAABB> = bodies.iter().map(|body| body.aabb).collect();
+        let mut pairs: Vec<_> = spatial_hash
+            .candidate_pairs(&aabbs)
+            .into_iter()
+            .map(|(a, b)| (&bodies[a], &bodies[b]))
+            .filter(|(a, b)| !(a.is_static && b.is_static) && filter(a.entity, b.entity))
+            .filter(|(a, b)| intersect_aabbs(&a.aabb, &b.aabb))
+            .map(|(a, b)| sorted_pair(a.entity, b.entity))
+            .collect();
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    fn random_box(rng: &mut Lcg) -> FlatAABB {
+        let center = Vec2::new(rng.range(0., 1000.), rng.range(0., 1000.));
+        let half_size = Vec2::new(rng.range(5., 60.), rng.range(5., 60.));
+        return FlatAABB {
+            min: center - half_size,
+            max: center + half_size,
+        };
+    }
+
+    #[test]
+    fn broad_phases_find_same_pairs() {
+        let mut rng = Lcg(7);
+        let mut bodies: Vec<Body> = (0..80)
+            .map(|i| Body {
+                entity: Entity::from_raw_u32(i).unwrap(),
+                aabb: random_box(&mut rng),
+                is_static: i % 5 == 0,
+            })
+            .collect();
+        // oversized for the hash grid, one static floor and one moving body
+        bodies.push(Body {
+            entity: Entity::from_raw_u32(80).unwrap(),
+            aabb: FlatAABB::new(-100., -50., 1100., 500.),
+            is_static: true,
+        });
+        bodies.push(Body {
+            entity: Entity::from_raw_u32(81).unwrap(),
+            aabb: FlatAABB::new(200., 200., 1200., 900.),
+            is_static: false,
+        });
+
+        let mut spatial_hash = SpatialHash::new(100.);
+        let mut dynamic_tree = DynamicTree::new(10.);
+        let mut sweep_and_prune = SweepAndPrune::default();
+
+        let mut reinserted = 0;
+        for step in 0..30 {
+            for body in bodies.iter() {
+                if dynamic_tree.update(body.entity, body.aabb, body.is_static) && step > 0 {
+                    reinserted += 1;
+                }
+                sweep_and_prune.update(body.entity, body.aabb, body.is_static);
+            }
+
+            let expected = brute_force_pairs(&bodies);
+            assert!(!expected.is_empty());
+            assert_eq!(
+                spatial_hash_pairs(&mut spatial_hash, &bodies),
+                expected,
+                "hash, step {step}"
+            );
+            assert_eq!(
+                dynamic_tree.find_pairs(filter),
+                expected,
+                "tree, step {step}"
+            );
+            assert_eq!(
+                sweep_and_prune.find_pairs(filter),
+                expected,
+                "sweep, step {step}"
+            );
+
+            // small moves stay inside the fattened leaves, some bodies jump far
+            // so the tree has to reinsert and rebalance
+            for body in bodies.iter_mut().filter(|body| !body.is_static) {
+                let motion = if rng.next() < 0.2 {
+                    Vec2::new(rng.range(-400., 400.), rng.range(-400., 400.))
+                } else {
+                    Vec2::new(rng.range(-8., 8.), rng.range(-8., 8.))
+                };
+                body.aabb.min += motion;
+                body.aabb.max += motion;
+            }
         }
+        assert!(reinserted > 0);
     }
 }
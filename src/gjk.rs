@@ -0,0 +1,577 @@
+use bevy::prelude::*;
+
+use crate::{
+    collisions::{CollisionDetails, Shape},
+    helpers::{get_global_vertices, to_vec2},
+};
+
+const MAX_GJK_ITERATIONS: usize = 32;
+const MAX_EPA_ITERATIONS: usize = 32;
+const GJK_TOLERANCE: f32 = 1e-4;
+const EPA_TOLERANCE: f32 = 1e-3;
+
+/// Convex hull of `points` inflated by `radius`.
+pub struct ConvexCore {
+    pub points: Vec<Vec2>,
+    pub radius: f32,
+}
+
+impl ConvexCore {
+    /// Index of the point furthest in `direction`.
+    pub fn support(&self, direction: Vec2) -> usize {
+        let mut best = 0;
+        let mut best_projection = f32::MIN;
+        for (i, point) in self.points.iter().enumerate() {
+            let projection = point.dot(direction);
+            if projection > best_projection {
+                best_projection = projection;
+                best = i;
+            }
+        }
+        return best;
+    }
+}
+
+/// Global convex core of the shape, `None` for shapes that are not convex.
+pub fn convex_core(shape: &Shape, transform: &Transform) -> Option<ConvexCore> {
+    match shape {
+        Shape::Circle(circle_params) => Some(ConvexCore {
+            points: vec![to_vec2(&transform.translation)],
+            radius: circle_params.radius,
+        }),
+        Shape::Capsule(capsule_params) => {
+            let (a, b) = capsule_params.segment(transform);
+            Some(ConvexCore {
+                points: vec![a, b],
+                radius: capsule_params.radius,
+            })
+        }
+        Shape::Box(_) | Shape::Polygon(_) | Shape::Segment(_) => Some(ConvexCore {
+            points: get_global_vertices(transform, shape.verticies()?),
+            radius: 0.,
+        }),
+        Shape::Chain(_) | Shape::Compound(_) => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SimplexVertex {
+    /// support point on A
+    a: Vec2,
+    /// support point on B
+    b: Vec2,
+    /// b - a, point of the Minkowski difference
+    w: Vec2,
+    index_a: usize,
+    index_b: usize,
+    /// barycentric coordinate of the closest point
+    t: f32,
+}
+
+fn simplex_vertex(core_a: &ConvexCore, core_b: &ConvexCore, direction: Vec2) -> SimplexVertex {
+    let index_a = core_a.support(-direction);
+    let index_b = core_b.support(direction);
+    let a = core_a.points[index_a];
+    let b = core_b.points[index_b];
+    SimplexVertex {
+        a,
+        b,
+        w: b - a,
+        index_a,
+        index_b,
+        t: 1.,
+    }
+}
+
+/// Reduces the simplex to the feature closest to the origin.
+fn solve_simplex(simplex: &mut Vec<SimplexVertex>) {
+    match simplex.len() {
+        1 => simplex[0].t = 1.,
+        2 => {
+            let (w1, w2) = (simplex[0].w, simplex[1].w);
+            let e12 = w2 - w1;
+
+            let d12_2 = -w1.dot(e12);
+            if d12_2 <= 0. {
+                simplex.truncate(1);
+                simplex[0].t = 1.;
+                return;
+            }
+
+            let d12_1 = w2.dot(e12);
+            if d12_1 <= 0. {
+                simplex.swap_remove(0);
+                simplex[0].t = 1.;
+                return;
+            }
+
+            let inv = 1. / (d12_1 + d12_2);
+            simplex[0].t = d12_1 * inv;
+            simplex[1].t = d12_2 * inv;
+        }
+        3 => {
+            let (w1, w2, w3) = (simplex[0].w, simplex[1].w, simplex[2].w);
+
+            let e12 = w2 - w1;
+            let d12_1 = w2.dot(e12);
+            let d12_2 = -w1.dot(e12);
+
+            let e13 = w3 - w1;
+            let d13_1 = w3.dot(e13);
+            let d13_2 = -w1.dot(e13);
+
+            let e23 = w3 - w2;
+            let d23_1 = w3.dot(e23);
+            let d23_2 = -w2.dot(e23);
+
+            let n123 = e12.perp_dot(e13);
+            let d123_1 = n123 * w2.perp_dot(w3);
+            let d123_2 = n123 * w3.perp_dot(w1);
+            let d123_3 = n123 * w1.perp_dot(w2);
+
+            let keep = |simplex: &mut Vec<SimplexVertex>, kept: &[(usize, f32)]| {
+                let vertices: Vec<SimplexVertex> = kept
+                    .iter()
+                    .map(|&(i, t)| SimplexVertex { t, ..simplex[i] })
+                    .collect();
+                *simplex = vertices;
+            };
+
+            if d12_2 <= 0. && d13_2 <= 0. {
+                keep(simplex, &[(0, 1.)]);
+            } else if d12_1 > 0. && d12_2 > 0. && d123_3 <= 0. {
+                let inv = 1. / (d12_1 + d12_2);
+                keep(simplex, &[(0, d12_1 * inv), (1, d12_2 * inv)]);
+            } else if d13_1 > 0. && d13_2 > 0. && d123_2 <= 0. {
+                let inv = 1. / (d13_1 + d13_2);
+                keep(simplex, &[(0, d13_1 * inv), (2, d13_2 * inv)]);
+            } else if d12_1 <= 0. && d23_2 <= 0. {
+                keep(simplex, &[(1, 1.)]);
+            } else if d13_1 <= 0. && d23_1 <= 0. {
+                keep(simplex, &[(2, 1.)]);
+            } else if d23_1 > 0. && d23_2 > 0. && d123_1 <= 0. {
+                let inv = 1. / (d23_1 + d23_2);
+                keep(simplex, &[(1, d23_1 * inv), (2, d23_2 * inv)]);
+            } else {
+                // origin is inside the triangle
+                let inv = 1. / (d123_1 + d123_2 + d123_3);
+                simplex[0].t = d123_1 * inv;
+                simplex[1].t = d123_2 * inv;
+                simplex[2].t = d123_3 * inv;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn closest_point(simplex: &[SimplexVertex]) -> Vec2 {
+    return simplex.iter().map(|v| v.w * v.t).sum();
+}
+
+/// Result of GJK between two convex cores, radii are not included.
+pub struct GjkOutput {
+    /// distance between the cores, 0 when they overlap
+    pub distance: f32,
+    pub point_a: Vec2,
+    pub point_b: Vec2,
+    pub overlapping: bool,
+    simplex: Vec<SimplexVertex>,
+}
+
+/// Closest points between convex hulls of two cores.
+pub fn gjk(core_a: &ConvexCore, core_b: &ConvexCore) -> GjkOutput {
+    let mut simplex = vec![simplex_vertex(core_a, core_b, Vec2::X)];
+    let mut overlapping = false;
+
+    for _iteration in 0..MAX_GJK_ITERATIONS {
+        solve_simplex(&mut simplex);
+
+        if simplex.len() == 3 {
+            overlapping = true;
+            break;
+        }
+
+        let closest = closest_point(&simplex);
+        if closest.length_squared() < GJK_TOLERANCE * GJK_TOLERANCE {
+            // origin lies on the simplex, shapes are touching
+            overlapping = true;
+            break;
+        }
+
+        let direction = -closest;
+        let vertex = simplex_vertex(core_a, core_b, direction);
+
+        // stop when the new vertex is already used or brings no progress
+        let duplicate = simplex
+            .iter()
+            .any(|v| v.index_a == vertex.index_a && v.index_b == vertex.index_b);
+        let progress = vertex.w.dot(direction) - closest.dot(direction);
+        if duplicate || progress <= GJK_TOLERANCE * closest.length() {
+            break;
+        }
+
+        simplex.push(vertex);
+    }
+
+    let point_a: Vec2 = simplex.iter().map(|v| v.a * v.t).sum();
+    let point_b: Vec2 = simplex.iter().map(|v| v.b * v.t).sum();
+    let distance = if overlapping {
+        0.
+    } else {
+        point_a.distance(point_b)
+    };
+
+    return GjkOutput {
+        distance,
+        point_a,
+        point_b,
+        overlapping,
+        simplex,
+    };
+}
+
+/// Expanding polytope algorithm, returns normal from A to B and depth.
+fn epa(core_a: &ConvexCore, core_b: &ConvexCore, simplex: &[SimplexVertex]) -> (Vec2, f32) {
+    let mut polytope: Vec<Vec2> = simplex.iter().map(|v| v.w).collect();
+
+    // touching shapes can end with a smaller simplex, grow it to a triangle
+    if polytope.len() == 1 {
+        for direction in [Vec2::X, -Vec2::X, Vec2::Y, -Vec2::Y] {
+            let w = simplex_vertex(core_a, core_b, direction).w;
+            if !polytope
+                .iter()
+                .any(|p| p.distance_squared(w) < GJK_TOLERANCE)
+            {
+                polytope.push(w);
+                break;
+            }
+        }
+    }
+    if polytope.len() == 2 {
+        let edge = polytope[1] - polytope[0];
+        for direction in [edge.perp(), -edge.perp()] {
+            let w = simplex_vertex(core_a, core_b, direction).w;
+            if (w - polytope[0]).perp_dot(edge).abs() > GJK_TOLERANCE {
+                polytope.push(w);
+                break;
+            }
+        }
+    }
+    if polytope.len() < 3 {
+        // both cores are the same point or collinear segments
+        let fallback = if polytope.len() == 2 {
+            (polytope[1] - polytope[0]).perp().normalize_or(Vec2::Y)
+        } else {
+            Vec2::Y
+        };
+        return (fallback, 0.);
+    }
+
+    // keep the polytope counter clockwise so edge normals point outwards
+    if (polytope[1] - polytope[0]).perp_dot(polytope[2] - polytope[0]) < 0. {
+        polytope.swap(1, 2);
+    }
+
+    let mut normal = Vec2::Y;
+    let mut depth = 0.;
+    for _iteration in 0..MAX_EPA_ITERATIONS {
+        let mut closest_edge = 0;
+        let mut closest_distance = f32::MAX;
+        for i in 0..polytope.len() {
+            let a = polytope[i];
+            let b = polytope[(i + 1) % polytope.len()];
+            let edge_normal = Vec2::new((b - a).y, -(b - a).x).normalize_or_zero();
+            let distance = edge_normal.dot(a);
+            if distance < closest_distance {
+                closest_distance = distance;
+                closest_edge = i;
+                normal = edge_normal;
+            }
+        }
+        depth = closest_distance;
+
+        let w = simplex_vertex(core_a, core_b, normal).w;
+        if w.dot(normal) - closest_distance < EPA_TOLERANCE {
+            break;
+        }
+        polytope.insert(closest_edge + 1, w);
+    }
+
+    // Minkowski difference is B - A, moving B by -normal * depth separates
+    // the cores, so the normal from A to B points the other way
+    return (-normal, depth);
+}
+
+/// Collision of two convex cores with GJK and EPA.
+pub fn gjk_collide(core_a: &ConvexCore, core_b: &ConvexCore) -> Option<CollisionDetails> {
+    let output = gjk(core_a, core_b);
+    let radius = core_a.radius + core_b.radius;
+
+    if !output.overlapping {
+        if output.distance >= radius {
+            return None;
+        }
+
+        let normal = (output.point_b - output.point_a) / output.distance;
+        return Some(CollisionDetails {
+            penetration_depth: radius - output.distance,
+            collision_normal: normal,
+        });
+    }
+
+    let (normal, depth) = epa(core_a, core_b, &output.simplex);
+    if depth + radius <= GJK_TOLERANCE {
+        // only touching, same as SAT
+        return None;
+    }
+
+    return Some(CollisionDetails {
+        penetration_depth: depth + radius,
+        collision_normal: normal,
+    });
+}
+
+/// Closest points of two shapes, on their surfaces.
+#[derive(Clone, Copy, Debug)]
+pub struct ClosestPoints {
+    /// Gap between the surfaces, 0 or less when the shapes touch.
+    pub distance: f32,
+    pub point_a: Vec2,
+    pub point_b: Vec2,
+    /// Direction from `point_a` to `point_b`.
+    pub normal: Vec2,
+}
+
+/// Closest points of two shapes over all their convex pieces.
+pub fn closest_points(
+    shape_a: &Shape,
+    transform_a: &Transform,
+    shape_b: &Shape,
+    transform_b: &Transform,
+) -> Option<ClosestPoints> {
+    let mut closest: Option<ClosestPoints> = None;
+    for (_index_a, local_a, sub_shape_a) in shape_a.sub_shapes() {
+        let Some(core_a) = convex_core(&sub_shape_a, &transform_a.mul_transform(local_a)) else {
+            continue;
+        };
+        for (_index_b, local_b, sub_shape_b) in shape_b.sub_shapes() {
+            let Some(core_b) = convex_core(&sub_shape_b, &transform_b.mul_transform(local_b))
+            else {
+                continue;
+            };
+
+            let output = gjk(&core_a, &core_b);
+            let distance = output.distance - core_a.radius - core_b.radius;
+            if closest.is_some_and(|closest| closest.distance <= distance) {
+                continue;
+            }
+            let normal = (output.point_b - output.point_a).normalize_or_zero();
+            closest = Some(ClosestPoints {
+                distance,
+                point_a: output.point_a + normal * core_a.radius,
+                point_b: output.point_b - normal * core_b.radius,
+                normal,
+            });
+        }
+    }
+    return closest;
+}
+
+/// Distance and direction from `shape_a` to `shape_b` at the closest points.
+pub fn shape_distance(
+    shape_a: &Shape,
+    transform_a: &Transform,
+    shape_b: &Shape,
+    transform_b: &Transform,
+) -> Option<(f32, Vec2)> {
+    return closest_points(shape_a, transform_a, shape_b, transform_b)
+        .map(|closest| (closest.distance, closest.normal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::{intersect_circle_polygon, intersects_polygons},
+        flat_body::{BoxParams, CapsuleParams, CircleParams, PolygonParams},
+        helpers::vertices_center,
+    };
+
+    fn assert_same(sat: Option<CollisionDetails>, gjk: Option<CollisionDetails>) {
+        match (sat, gjk) {
+            (None, None) => {}
+            (Some(sat), Some(gjk)) => {
+                assert!(
+                    sat.collision_normal.distance(gjk.collision_normal) < 1e-3,
+                    "normal sat {} gjk {}",
+                    sat.collision_normal,
+                    gjk.collision_normal
+                );
+                assert!(
+                    (sat.penetration_depth - gjk.penetration_depth).abs() < 1e-2,
+                    "depth sat {} gjk {}",
+                    sat.penetration_depth,
+                    gjk.penetration_depth
+                );
+            }
+            (sat, gjk) => panic!(
+                "sat found collision: {}, gjk found collision: {}",
+                sat.is_some(),
+                gjk.is_some()
+            ),
+        }
+    }
+
+    fn transform(x: f32, y: f32, angle: f32) -> Transform {
+        Transform::from_xyz(x, y, 0.).with_rotation(Quat::from_rotation_z(angle))
+    }
+
+    #[test]
+    fn polygon_polygon_matches_sat() {
+        let box_shape = Shape::Box(BoxParams::new(100., 60.));
+        let hexagon = Shape::Polygon(PolygonParams::regular(6, 40.));
+
+        let cases = [
+            (transform(0., 0., 0.), transform(85., 10., 0.)),
+            (transform(0., 0., 0.3), transform(-20., 70., 1.1)),
+            (transform(5., -3., 2.), transform(40., -50., -0.4)),
+            (transform(0., 0., 0.), transform(200., 0., 0.)),
+        ];
+
+        for (transform_a, transform_b) in cases {
+            let vertices_a = get_global_vertices(&transform_a, box_shape.verticies().unwrap());
+            let vertices_b = get_global_vertices(&transform_b, hexagon.verticies().unwrap());
+            let sat = intersects_polygons(
+                &vertices_a,
+                &vertices_center(&vertices_a),
+                &vertices_b,
+                &vertices_center(&vertices_b),
+            );
+
+            let gjk = gjk_collide(
+                &convex_core(&box_shape, &transform_a).unwrap(),
+                &convex_core(&hexagon, &transform_b).unwrap(),
+            );
+
+            assert_same(sat, gjk);
+        }
+    }
+
+    #[test]
+    fn circle_polygon_matches_sat() {
+        let box_shape = Shape::Box(BoxParams::new(100., 60.));
+        let circle = Shape::Circle(CircleParams::new(25.));
+
+        let cases = [
+            (transform(0., 0., 0.), transform(0., 50., 0.)),
+            (transform(0., 0., 0.5), transform(60., 30., 0.)),
+            (transform(0., 0., 0.), transform(65., 40., 0.)),
+            (transform(0., 0., 0.), transform(0., 100., 0.)),
+        ];
+
+        for (transform_a, transform_b) in cases {
+            let vertices_a = get_global_vertices(&transform_a, box_shape.verticies().unwrap());
+            let mut sat = intersect_circle_polygon(
+                &to_vec2(&transform_b.translation),
+                25.,
+                &vertices_a,
+                &vertices_center(&vertices_a),
+            );
+            if let Some(sat) = &mut sat {
+                sat.collision_normal *= -1.;
+            }
+
+            let gjk = gjk_collide(
+                &convex_core(&box_shape, &transform_a).unwrap(),
+                &convex_core(&circle, &transform_b).unwrap(),
+            );
+
+            assert_same(sat, gjk);
+        }
+    }
+
+    fn assert_collision(gjk: Option<CollisionDetails>, normal: Vec2, depth: f32) {
+        let gjk = gjk.expect("gjk found no collision");
+        assert!(
+            gjk.collision_normal.distance(normal) < 1e-3,
+            "normal expected {} gjk {}",
+            normal,
+            gjk.collision_normal
+        );
+        assert!(
+            (gjk.penetration_depth - depth).abs() < 1e-2,
+            "depth expected {} gjk {}",
+            depth,
+            gjk.penetration_depth
+        );
+    }
+
+    fn collide_shapes(
+        shape_a: &Shape,
+        transform_a: Transform,
+        shape_b: &Shape,
+        transform_b: Transform,
+    ) -> Option<CollisionDetails> {
+        return gjk_collide(
+            &convex_core(shape_a, &transform_a).unwrap(),
+            &convex_core(shape_b, &transform_b).unwrap(),
+        );
+    }
+
+    // vertical capsule with the inner segment from (0, -50) to (0, 50)
+    fn capsule() -> Shape {
+        return Shape::Capsule(CapsuleParams::new(20., 100.));
+    }
+
+    #[test]
+    fn capsule_circle_matches_analytic() {
+        let circle = Shape::Circle(CircleParams::new(25.));
+        let origin = transform(0., 0., 0.);
+
+        // beside the segment, 40 away from it
+        let gjk = collide_shapes(&capsule(), origin, &circle, transform(40., 10., 0.));
+        assert_collision(gjk, Vec2::X, 5.);
+
+        // past the upper end, 40 away from the end point along (0.6, 0.8)
+        let gjk = collide_shapes(&capsule(), origin, &circle, transform(24., 82., 0.));
+        assert_collision(gjk, Vec2::new(0.6, 0.8), 5.);
+
+        let gjk = collide_shapes(&capsule(), origin, &circle, transform(30., 90., 0.));
+        assert!(gjk.is_none());
+    }
+
+    #[test]
+    fn capsule_box_matches_analytic() {
+        let box_shape = Shape::Box(BoxParams::new(100., 60.));
+        let origin = transform(0., 0., 0.);
+
+        // upright capsule next to the right side of the box
+        let gjk = collide_shapes(&box_shape, origin, &capsule(), transform(65., 0., 0.));
+        assert_collision(gjk, Vec2::X, 5.);
+
+        // capsule lying on top of the box
+        let lying = transform(0., 45., std::f32::consts::FRAC_PI_2);
+        let gjk = collide_shapes(&box_shape, origin, &capsule(), lying);
+        assert_collision(gjk, Vec2::Y, 5.);
+
+        let gjk = collide_shapes(&box_shape, origin, &capsule(), transform(75., 0., 0.));
+        assert!(gjk.is_none());
+    }
+
+    #[test]
+    fn capsule_capsule_matches_analytic() {
+        let origin = transform(0., 0., 0.);
+
+        // parallel segments 35 apart
+        let gjk = collide_shapes(&capsule(), origin, &capsule(), transform(35., 10., 0.));
+        assert_collision(gjk, Vec2::X, 5.);
+
+        // crossed, the upper end point is 35 below the lying segment
+        let lying = transform(0., 85., std::f32::consts::FRAC_PI_2);
+        let gjk = collide_shapes(&capsule(), origin, &capsule(), lying);
+        assert_collision(gjk, Vec2::Y, 5.);
+
+        let gjk = collide_shapes(&capsule(), origin, &capsule(), transform(45., 0., 0.));
+        assert!(gjk.is_none());
+    }
+}
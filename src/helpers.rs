@@ -10,23 +10,25 @@ pub fn to_vec3(vec2: &Vec2) -> Vec3 {
     Vec3::new(vec2.x, vec2.y, 0.)
 }
 
-pub fn get_global_vertices(transform: &Transform, verticies: &[Vec2; 4]) -> [Vec2; 4] {
-    let mut new_verticies: [Vec2; 4] = [Vec2::ZERO; 4];
+pub fn get_global_vertices(transform: &Transform, verticies: &[Vec2]) -> Vec<Vec2> {
+    let mut new_verticies: Vec<Vec2> = Vec::with_capacity(verticies.len());
 
-    for (i, vertex) in verticies.iter().enumerate() {
+    for vertex in verticies.iter() {
         // Transform the vertex by applying rotation and translation
         let rotated = transform
             .rotation
             .mul_vec3(Vec3::new(vertex.x, vertex.y, 0.));
         let transformed_point = transform.translation + rotated;
-        new_verticies[i] = to_vec2(&transformed_point);
+        new_verticies.push(to_vec2(&transformed_point));
     }
 
     return new_verticies;
 }
 
-pub fn nearly_equal(a: f32, b: f32) -> bool {
-    return (a - b).abs() < MIN_TRESHOLD_DISTANCE;
+/// Average of the vertices, for convex polygons it always lies inside.
+pub fn vertices_center(verticies: &[Vec2]) -> Vec2 {
+    let sum: Vec2 = verticies.iter().sum();
+    return sum / verticies.len() as f32;
 }
 
 pub fn nearly_equal_vec(a: &Vec2, b: &Vec2) -> bool {
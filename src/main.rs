@@ -1,21 +1,62 @@
 use std::time::{Duration, SystemTime};
 
-use bevy::{color::palettes::css::WHITE, prelude::*};
+use bevy::{
+    asset::RenderAssetUsages,
+    color::palettes::css::{WHITE, YELLOW},
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
 mod flat_body;
 mod mouse_position;
+mod one_way_platform;
+mod point_query;
 use flat_body::FlatBody;
+mod ccd;
+mod collision_events;
+mod collision_layers;
 mod collisions;
+mod contact_hooks;
+mod contacts;
+mod convex_decomposition;
+mod dynamic_tree;
 mod flat_aabb;
 mod flat_world;
+mod gjk;
 mod helpers;
+mod ray_cast;
+mod shape_cast;
+mod solver;
+mod spatial_hash;
+mod spatial_query;
+mod sweep_and_prune;
 
 use crate::{
+    collision_events::{
+        CollisionEnded, CollisionEventWriters, CollisionStarted, OnCollisionEnd, OnCollisionStart,
+    },
+    collision_layers::CollisionLayers,
     collisions::{Collider, Shape},
-    flat_body::{BoxParams, CircleParams, FlatBodyType, handle_physics_step, on_flat_body_added},
-    flat_world::{FlatWorld, broad_phase, narrow_phase},
-    mouse_position::{MousePositionPlugin, MyWorldCoords},
+    contact_hooks::{ContactHooks, ConveyorBelt},
+    convex_decomposition::triangulate,
+    flat_aabb::FlatAABB,
+    flat_body::{
+        BoxParams, CapsuleParams, ChainParams, CircleParams, CompoundChild, CompoundParams,
+        FlatBodyType, PolygonParams, SegmentParams, on_flat_body_added,
+    },
+    flat_world::{
+        BroadPhaseKind, FlatWorld, PairFilters, insert_into_query_tree, refresh_query_tree,
+        sub_step, update_query_tree,
+    },
+    helpers::{get_global_vertices, to_vec2, vertices_center},
+    mouse_position::{MousePositionPlugin, MyWorldCoords, PickedBody},
+    one_way_platform::OneWayPlatform,
+    solver::{PositionCorrection, SolverSettings},
+    spatial_query::{SpatialQuery, SpatialQueryFilter},
 };
 
+/// Collision layer of the fast bullets, other bodies stay on the first layer.
+const BULLET_LAYER: u32 = 1 << 1;
+
 fn main() {
     App::new()
         .add_plugins((DefaultPlugins, MousePositionPlugin))
@@ -28,13 +69,36 @@ fn main() {
         .insert_resource(DiagnosisConfig {
             timer: Timer::new(Duration::from_secs(1), TimerMode::Repeating),
         })
+        .init_resource::<ContactHooks>()
         .add_systems(Startup, (setup, spawn_text_in_ui).chain())
         .add_systems(
             Update,
-            (spawn_physics_object, diagnosis_ui, draw_line_for_circle),
+            (
+                spawn_physics_object,
+                diagnosis_ui,
+                switch_broad_phase,
+                switch_position_correction,
+                draw_line_for_circle,
+                draw_segments_and_chains,
+                draw_sensor_overlaps,
+                refresh_query_tree,
+                // spatial queries see bodies where they are in this frame
+                (
+                    draw_ground_ray,
+                    draw_picked_body,
+                    draw_nearest_surface,
+                    explode,
+                )
+                    .after(refresh_query_tree),
+            ),
         )
         .add_systems(FixedUpdate, (world_step).chain())
+        .add_message::<CollisionStarted>()
+        .add_message::<CollisionEnded>()
         .add_observer(on_flat_body_added)
+        .add_observer(insert_into_query_tree)
+        .add_observer(highlight_sensor_visitor)
+        .add_observer(restore_sensor_visitor)
         .run();
 }
 
@@ -48,6 +112,14 @@ struct DiagnosisConfig {
 struct BodyCountText {}
 #[derive(Component)]
 struct StepTimeText {}
+#[derive(Component)]
+struct BroadPhaseText {}
+#[derive(Component)]
+struct PositionCorrectionText {}
+
+/// Material a body had before it entered a sensor.
+#[derive(Component)]
+struct SensorVisitor(Handle<ColorMaterial>);
 
 fn spawn_text_in_ui(mut commands: Commands) {
     commands
@@ -70,9 +142,63 @@ fn spawn_text_in_ui(mut commands: Commands) {
             builder
                 .spawn((Text::new("step time micros: "),))
                 .with_child((TextSpan::default(), StepTimeText {}));
+            builder
+                .spawn((Text::new("broad phase (B): "),))
+                .with_child((
+                    TextSpan::new(format!("{:?}", BroadPhaseKind::default())),
+                    BroadPhaseText {},
+                ));
+            builder
+                .spawn((Text::new("position correction (P): "),))
+                .with_child((
+                    TextSpan::new(format!(
+                        "{:?}",
+                        SolverSettings::default().position_correction
+                    )),
+                    PositionCorrectionText {},
+                ));
         });
 }
 
+fn switch_broad_phase(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut flat_world: ResMut<FlatWorld>,
+    mut broad_phase_query: Query<&mut TextSpan, With<BroadPhaseText>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyB) {
+        return;
+    }
+
+    flat_world.broad_phase = match flat_world.broad_phase {
+        BroadPhaseKind::BruteForce => BroadPhaseKind::SpatialHash,
+        BroadPhaseKind::SpatialHash => BroadPhaseKind::DynamicTree,
+        BroadPhaseKind::DynamicTree => BroadPhaseKind::SweepAndPrune,
+        BroadPhaseKind::SweepAndPrune => BroadPhaseKind::BruteForce,
+    };
+    for mut span in &mut broad_phase_query {
+        **span = format!("{:?}", flat_world.broad_phase);
+    }
+}
+
+fn switch_position_correction(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut flat_world: ResMut<FlatWorld>,
+    mut position_correction_query: Query<&mut TextSpan, With<PositionCorrectionText>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+
+    flat_world.solver.position_correction = match flat_world.solver.position_correction {
+        PositionCorrection::Baumgarte => PositionCorrection::SplitImpulse,
+        PositionCorrection::SplitImpulse => PositionCorrection::NonLinearGaussSeidel,
+        PositionCorrection::NonLinearGaussSeidel => PositionCorrection::Baumgarte,
+    };
+    for mut span in &mut position_correction_query {
+        **span = format!("{:?}", flat_world.solver.position_correction);
+    }
+}
+
 fn diagnosis_ui(
     mut body_count_query: Query<&mut TextSpan, (With<BodyCountText>, Without<StepTimeText>)>,
     mut step_time_query: Query<&mut TextSpan, (With<StepTimeText>, Without<BodyCountText>)>,
@@ -98,6 +224,7 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    mut contact_hooks: ResMut<ContactHooks>,
 ) {
     commands.spawn(Camera2d);
 
@@ -130,12 +257,98 @@ fn setup(
         Collider::new(Shape::Box(BoxParams::new(400., 30.))),
     ));
 
+    // One way platform, bodies jump through from below
+    commands.spawn((
+        Mesh2d(meshes.add(Rectangle::new(200.0, 10.))),
+        MeshMaterial2d(materials.add(Color::srgb(0., 1., 0.5))),
+        Transform::from_xyz(50.0 * 5., 50.0 * -6.0, 0.0),
+        FlatBody::new(1., FlatBodyType::Static, 0.5),
+        Collider::new(Shape::Box(BoxParams::new(200., 10.))),
+        OneWayPlatform::default(),
+    ));
+
+    // Floor made of short segments, bodies slide over the seams
+    let floor = (-7..=7)
+        .map(|i| Vec2::new(50. * i as f32, 0.))
+        .collect::<Vec<_>>();
     commands.spawn((
-        Mesh2d(meshes.add(Rectangle::new(700.0, 30.))),
-        MeshMaterial2d(materials.add(Color::srgb(0., 0., 1.))),
         Transform::from_xyz(0.0, 50.0 * -9.0, 0.0),
         FlatBody::new(1., FlatBodyType::Static, 0.5),
-        Collider::new(Shape::Box(BoxParams::new(700., 30.))),
+        Collider::new(Shape::Chain(ChainParams::new(floor))),
+    ));
+
+    // Trigger zone, bodies fall through it and are connected to it and highlighted while inside
+    commands.spawn((
+        Mesh2d(meshes.add(Rectangle::new(100.0, 100.))),
+        MeshMaterial2d(materials.add(Color::srgba(1., 1., 0., 0.2))),
+        Transform::from_xyz(50.0 * -6., 50.0 * -7.6, 0.0),
+        FlatBody::new(1., FlatBodyType::Static, 0.5),
+        Collider::sensor(Shape::Box(BoxParams::new(100., 100.))),
+    ));
+
+    // Bowl drawn as one concave outline
+    let bowl = vec![
+        Vec2::new(-120., 0.),
+        Vec2::new(120., 0.),
+        Vec2::new(120., 80.),
+        Vec2::new(90., 80.),
+        Vec2::new(90., 30.),
+        Vec2::new(-90., 30.),
+        Vec2::new(-90., 80.),
+        Vec2::new(-120., 80.),
+    ];
+    let bowl_params =
+        CompoundParams::from_concave_polygon(&bowl).expect("bowl is a simple polygon");
+    // the pieces are centered on the centroid, move the outline and body with them
+    let bowl_mesh: Vec<Vec2> = bowl
+        .iter()
+        .map(|vertex| *vertex - bowl_params.centroid)
+        .collect();
+    let bowl_position = Vec2::new(-100.0, 50.0 * -6.0) + bowl_params.centroid;
+    commands.spawn((
+        Mesh2d(meshes.add(concave_polygon_mesh(&bowl_mesh))),
+        MeshMaterial2d(materials.add(Color::srgb(0., 0., 1.))),
+        Transform::from_xyz(bowl_position.x, bowl_position.y, 0.0),
+        FlatBody::new(1., FlatBodyType::Static, 0.5),
+        Collider::new(Shape::Compound(bowl_params)),
+    ));
+
+    // Ledge, a conveyor belt carrying bodies to the left
+    let ledge = commands
+        .spawn((
+            Transform::from_xyz(-150.0, 50.0 * 2.0, 0.0),
+            FlatBody::new(1., FlatBodyType::Static, 0.5),
+            Collider::new(Shape::Segment(SegmentParams::new(
+                Vec2::new(-100., 0.),
+                Vec2::new(100., 0.),
+            ))),
+        ))
+        .id();
+    contact_hooks.register(ConveyorBelt {
+        entity: ledge,
+        speed: -100.,
+    });
+
+    // Wedge, centered on its centroid so the body origin lies inside it
+    let wedge_outline = [
+        Vec2::new(-100., -40.),
+        Vec2::new(100., -40.),
+        Vec2::new(100., 40.),
+    ];
+    let wedge_centroid = vertices_center(&wedge_outline);
+    let wedge: Vec<Vec2> = wedge_outline.iter().map(|v| *v - wedge_centroid).collect();
+    commands.spawn((
+        Mesh2d(meshes.add(ConvexPolygon::new_unchecked(wedge.clone()))),
+        MeshMaterial2d(materials.add(Color::srgb(0., 0., 1.))),
+        Transform::from_xyz(
+            250.0 + wedge_centroid.x,
+            50.0 * -8.0 + wedge_centroid.y,
+            0.0,
+        ),
+        FlatBody::new(1., FlatBodyType::Static, 0.5),
+        Collider::new(Shape::Polygon(
+            PolygonParams::new(wedge).expect("wedge is convex"),
+        )),
     ));
 
     // commands.spawn((
@@ -147,11 +360,31 @@ fn setup(
     // ));
 }
 
+fn concave_polygon_mesh(outline: &[Vec2]) -> Mesh {
+    let positions: Vec<[f32; 3]> = outline.iter().map(|v| [v.x, v.y, 0.]).collect();
+    let indices: Vec<u32> = triangulate(outline)
+        .expect("outline should be a simple polygon")
+        .into_iter()
+        .flatten()
+        .map(|i| i as u32)
+        .collect();
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; positions.len()])
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.]; positions.len()])
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
 fn spawn_physics_object(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
     cursor_position: Res<MyWorldCoords>,
 ) {
     let random_red = rand::random::<f32>();
@@ -176,43 +409,105 @@ fn spawn_physics_object(
             FlatBody::new(1., FlatBodyType::Dynamic, 0.5),
             Collider::new(Shape::Box(BoxParams::new(100., 100.))),
         ));
+    } else if buttons.just_pressed(MouseButton::Middle) {
+        // triangle
+        let polygon = PolygonParams::regular(3, 60.);
+        commands.spawn((
+            Mesh2d(meshes.add(ConvexPolygon::new_unchecked(polygon.verticies.clone()))),
+            MeshMaterial2d(materials.add(Color::srgb(random_red, random_green, random_blue))),
+            Transform::from_xyz(cursor_position.0.x, cursor_position.0.y, 0.0),
+            FlatBody::new(1., FlatBodyType::Dynamic, 0.5),
+            Collider::new(Shape::Polygon(polygon)),
+        ));
+    } else if keys.just_pressed(KeyCode::KeyC) {
+        // capsule
+        commands.spawn((
+            Mesh2d(meshes.add(Capsule2d::new(25., 60.))),
+            MeshMaterial2d(materials.add(Color::srgb(random_red, random_green, random_blue))),
+            Transform::from_xyz(cursor_position.0.x, cursor_position.0.y, 0.0),
+            FlatBody::new(1., FlatBodyType::Dynamic, 0.5),
+            Collider::new(Shape::Capsule(CapsuleParams::new(25., 60.))),
+        ));
+    } else if keys.just_pressed(KeyCode::KeyF) {
+        // fast bullet, needs ccd to not pass through walls
+        let mut bullet = FlatBody::new(0.1, FlatBodyType::Dynamic, 0.5);
+        bullet.linear_velocity = Vec2::new(4000., 0.);
+        bullet.ccd = true;
+        commands.spawn((
+            Mesh2d(meshes.add(Circle::new(5.0))),
+            MeshMaterial2d(materials.add(Color::srgb(1., 1., 0.))),
+            Transform::from_xyz(cursor_position.0.x, cursor_position.0.y, 0.0),
+            bullet,
+            Collider::new(Shape::Circle(CircleParams::new(5.))),
+            // bullets hit everything but each other
+            CollisionLayers::new(BULLET_LAYER, u32::MAX).with_group(-1),
+        ));
+    } else if keys.just_pressed(KeyCode::KeyL) {
+        // L shaped crate, `CompoundParams::new` moves the centroid to the body origin
+        let compound_params = CompoundParams::new(vec![
+            CompoundChild::new(Vec2::new(0., 0.), 0., Shape::Box(BoxParams::new(40., 120.))),
+            CompoundChild::new(
+                Vec2::new(40., -40.),
+                0.,
+                Shape::Box(BoxParams::new(40., 40.)),
+            ),
+        ]);
+        let children = compound_params.children.clone();
+        let position = cursor_position.0 + compound_params.centroid;
+        let material = materials.add(Color::srgb(random_red, random_green, random_blue));
+        commands
+            .spawn((
+                Transform::from_xyz(position.x, position.y, 0.0),
+                Visibility::default(),
+                FlatBody::new(1., FlatBodyType::Dynamic, 0.5),
+                Collider::new(Shape::Compound(compound_params)),
+            ))
+            .with_children(|parent| {
+                for child in children.iter() {
+                    if let Shape::Box(box_params) = &child.shape {
+                        parent.spawn((
+                            Mesh2d(meshes.add(Rectangle::new(box_params.width, box_params.height))),
+                            MeshMaterial2d(material.clone()),
+                            child.local_transform(),
+                        ));
+                    }
+                }
+            });
     }
 }
 
+/// One fixed step of the physics world.
 fn world_step(
     fixed_time: Res<Time<Fixed>>,
     mut query: Query<(Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    filters: PairFilters,
     mut flat_world: ResMut<FlatWorld>,
-    mut collision_entitties: Local<Vec<(Entity, Entity)>>,
+    mut collision_events: CollisionEventWriters,
 ) {
     let world_step_start = SystemTime::now();
     flat_world.body_count = query.count();
     let delta_time_origin = fixed_time.delta_secs();
-
     for _iteration in 0..flat_world.iterations {
         let delta_time = delta_time_origin / (flat_world.iterations as f32);
-        collision_entitties.clear();
-
-        // physics step
-        for (_entity, mut transform, mut flat_body, mut _collider) in query.iter_mut() {
-            if let FlatBodyType::Static = flat_body.body_type {
-                continue;
-            }
-
-            handle_physics_step(
-                &mut transform,
-                &mut flat_body,
-                &flat_world.gravity,
-                delta_time,
-            );
-        }
-
-        // Collision step
-        broad_phase(&mut query, &mut collision_entitties);
-        // collision resolve
-        narrow_phase(&mut query, &collision_entitties);
+        sub_step(&mut query, &filters, &mut flat_world, delta_time);
     }
 
+    // only the pairs touching at the end of the step count, so the messages
+    // agree with `CollidingEntities`
+    let mut started = Vec::new();
+    let mut ended = Vec::new();
+    let FlatWorld {
+        contacts,
+        collision_tracker,
+        ..
+    } = &mut *flat_world;
+    collision_tracker.update(contacts, &mut started, &mut ended);
+
+    update_query_tree(&query, &mut flat_world.query_tree);
+    collision_events.send(&flat_world.collision_tracker, started, ended, |entity| {
+        query.contains(entity)
+    });
+
     flat_world.world_step_time_s = world_step_start.elapsed().unwrap().as_micros();
 }
 
@@ -227,3 +522,237 @@ fn draw_line_for_circle(
         }
     }
 }
+
+fn draw_segments_and_chains(query: Query<(&Transform, &Collider)>, mut gizmos: Gizmos) {
+    for (transform, collider) in query.iter() {
+        let verticies: &[Vec2] = match &collider.shape {
+            Shape::Segment(segment_params) => &segment_params.verticies,
+            Shape::Chain(chain_params) => &chain_params.verticies,
+            _ => continue,
+        };
+        gizmos.linestrip_2d(get_global_vertices(transform, verticies), WHITE);
+    }
+}
+
+/// Lines between every sensor and the bodies overlapping it.
+fn draw_sensor_overlaps(
+    flat_world: Res<FlatWorld>,
+    transforms: Query<&Transform>,
+    mut gizmos: Gizmos,
+) {
+    for (entity_a, entity_b) in flat_world.contacts.sensor_overlaps() {
+        let Ok([transform_a, transform_b]) = transforms.get_many([entity_a, entity_b]) else {
+            continue;
+        };
+        gizmos.line_2d(
+            transform_a.translation.truncate(),
+            transform_b.translation.truncate(),
+            YELLOW,
+        );
+    }
+}
+
+/// Highlights bodies while they are inside a sensor.
+fn highlight_sensor_visitor(
+    event: On<OnCollisionStart>,
+    colliders: Query<&Collider>,
+    mut visitors: Query<&mut MeshMaterial2d<ColorMaterial>, Without<SensorVisitor>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut commands: Commands,
+) {
+    // the event is triggered on both entities, react on the sensor side
+    if !event.data.is_sensor
+        || !colliders
+            .get(event.entity)
+            .is_ok_and(|collider| collider.sensor)
+    {
+        return;
+    }
+    let Ok(mut material) = visitors.get_mut(event.other) else {
+        return;
+    };
+    commands
+        .entity(event.other)
+        .insert(SensorVisitor(material.0.clone()));
+    material.0 = materials.add(Color::from(YELLOW));
+}
+
+/// Gives bodies leaving a sensor their material back.
+fn restore_sensor_visitor(
+    event: On<OnCollisionEnd>,
+    colliders: Query<&Collider>,
+    mut visitors: Query<(&mut MeshMaterial2d<ColorMaterial>, &SensorVisitor)>,
+    mut commands: Commands,
+) {
+    if !event.data.is_sensor
+        || !colliders
+            .get(event.entity)
+            .is_ok_and(|collider| collider.sensor)
+    {
+        return;
+    }
+    let Ok((mut material, visitor)) = visitors.get_mut(event.other) else {
+        return;
+    };
+    material.0 = visitor.0.clone();
+    commands.entity(event.other).remove::<SensorVisitor>();
+}
+
+/// Ray and circle cast from the cursor down to the colliders below it.
+fn draw_ground_ray(
+    spatial_query: SpatialQuery,
+    cursor_position: Res<MyWorldCoords>,
+    picked_body: Res<PickedBody>,
+    colliders: Query<(&Transform, &Collider)>,
+    mut gizmos: Gizmos,
+) {
+    let filter = SpatialQueryFilter::from_mask(!BULLET_LAYER).with_excluded_entities(picked_body.0);
+    let origin = cursor_position.0;
+    let hits = spatial_query.ray_cast_all(origin, Vec2::NEG_Y, 1000., &filter);
+    if let Some(hit) = hits.first() {
+        gizmos.line_2d(origin, hit.point, WHITE);
+        gizmos.ray_2d(hit.point, hit.normal * 20., WHITE);
+        draw_sub_shape_aabb(&mut gizmos, &colliders, hit.entity, hit.sub_shape);
+    }
+    // colliders hidden behind the first one
+    for hit in hits.iter().skip(1) {
+        gizmos.circle_2d(hit.point, 4., WHITE.with_alpha(0.4));
+    }
+
+    let radius = 15.;
+    let start = origin + Vec2::new(radius * 3., 0.);
+    if let Some(hit) = spatial_query.shape_cast(
+        &Shape::Circle(CircleParams::new(radius)),
+        &Transform::from_translation(start.extend(0.)),
+        Vec2::NEG_Y,
+        1000.,
+        &filter,
+    ) {
+        let center = start + Vec2::NEG_Y * hit.distance;
+        gizmos.line_2d(start, center, WHITE);
+        gizmos.circle_2d(center, radius, WHITE);
+        gizmos.ray_2d(hit.point, hit.normal * 20., WHITE);
+        draw_sub_shape_aabb(&mut gizmos, &colliders, hit.entity, hit.sub_shape);
+    }
+}
+
+fn draw_sub_shape_aabb(
+    gizmos: &mut Gizmos,
+    colliders: &Query<(&Transform, &Collider)>,
+    entity: Entity,
+    sub_shape: usize,
+) {
+    let Ok((transform, collider)) = colliders.get(entity) else {
+        return;
+    };
+    let Some((_index, local, shape)) = collider.shape.sub_shapes().into_iter().nth(sub_shape)
+    else {
+        return;
+    };
+    draw_aabb(
+        gizmos,
+        &shape.get_aabb(&transform.mul_transform(local)),
+        WHITE,
+    );
+}
+
+fn draw_aabb(gizmos: &mut Gizmos, aabb: &FlatAABB, color: impl Into<Color>) {
+    gizmos.linestrip_2d(
+        [
+            aabb.min,
+            Vec2::new(aabb.max.x, aabb.min.y),
+            aabb.max,
+            Vec2::new(aabb.min.x, aabb.max.y),
+            aabb.min,
+        ],
+        color,
+    );
+}
+
+/// Outline around the body under the cursor and gaps to the bodies near it.
+fn draw_picked_body(
+    picked_body: Res<PickedBody>,
+    spatial_query: SpatialQuery,
+    query: Query<(&Transform, &Collider)>,
+    mut gizmos: Gizmos,
+) {
+    let Some(entity) = picked_body.0 else {
+        return;
+    };
+    let Ok((transform, collider)) = query.get(entity) else {
+        return;
+    };
+    let aabb = collider.shape.get_aabb(transform);
+    draw_aabb(&mut gizmos, &aabb, YELLOW);
+
+    let filter = SpatialQueryFilter::default().with_excluded_entities([entity]);
+    for other in spatial_query.overlap_aabb(&aabb.expanded(100.), &filter) {
+        if let Some(closest) = spatial_query.closest_points(entity, other) {
+            gizmos.line_2d(closest.point_a, closest.point_b, YELLOW);
+        }
+    }
+}
+
+/// Line from the cursor to the nearest collider surface.
+fn draw_nearest_surface(
+    spatial_query: SpatialQuery,
+    cursor_position: Res<MyWorldCoords>,
+    mut gizmos: Gizmos,
+) {
+    let Some(hit) = spatial_query.project_point(cursor_position.0, &SpatialQueryFilter::default())
+    else {
+        return;
+    };
+    gizmos.line_2d(cursor_position.0, hit.point, YELLOW);
+}
+
+/// Pushes dynamic bodies around the cursor away from it when E is pressed.
+fn explode(
+    keys: Res<ButtonInput<KeyCode>>,
+    cursor_position: Res<MyWorldCoords>,
+    spatial_query: SpatialQuery,
+    mut bodies: Query<(&Transform, &mut FlatBody)>,
+    mut gizmos: Gizmos,
+) {
+    let radius = 200.;
+    let center = cursor_position.0;
+    if !keys.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+    gizmos.circle_2d(center, radius, YELLOW);
+
+    let entities = spatial_query.overlap_shape(
+        &Shape::Circle(CircleParams::new(radius)),
+        &Transform::from_translation(center.extend(0.)),
+        &SpatialQueryFilter::default(),
+    );
+    let is_static = |bodies: &Query<(&Transform, &mut FlatBody)>, entity: Entity| {
+        return bodies.get(entity).is_ok_and(|(_transform, flat_body)| {
+            matches!(flat_body.body_type, FlatBodyType::Static)
+        });
+    };
+    for entity in entities {
+        let Ok((transform, _flat_body)) = bodies.get(entity) else {
+            continue;
+        };
+        if is_static(&bodies, entity) {
+            continue;
+        }
+        let offset = to_vec2(&transform.translation) - center;
+        // static bodies shield the ones behind them
+        if let Some(hit) = spatial_query.ray_cast(
+            center,
+            offset,
+            offset.length(),
+            &SpatialQueryFilter::default(),
+        ) && is_static(&bodies, hit.entity)
+        {
+            continue;
+        }
+        let falloff = 1. - (offset.length() / radius).min(1.);
+        let Ok((_transform, mut flat_body)) = bodies.get_mut(entity) else {
+            continue;
+        };
+        flat_body.linear_velocity += offset.normalize_or_zero() * 800. * falloff;
+    }
+}
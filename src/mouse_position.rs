@@ -1,8 +1,17 @@
 use bevy::{prelude::*, window::PrimaryWindow};
 
+use crate::{
+    flat_world::refresh_query_tree,
+    spatial_query::{SpatialQuery, SpatialQueryFilter},
+};
+
 #[derive(Resource, Default)]
 pub struct MyWorldCoords(pub Vec2);
 
+/// Collider under the cursor.
+#[derive(Resource, Default)]
+pub struct PickedBody(pub Option<Entity>);
+
 fn my_cursor_system(
     mut mycoords: ResMut<MyWorldCoords>,
     // query to get the window (so we can read the current cursor position)
@@ -28,11 +37,24 @@ fn my_cursor_system(
     }
 }
 
+fn pick_body_system(
+    mycoords: Res<MyWorldCoords>,
+    spatial_query: SpatialQuery,
+    mut picked_body: ResMut<PickedBody>,
+) {
+    let entities = spatial_query.point_query(mycoords.0, &SpatialQueryFilter::default());
+    picked_body.0 = entities.first().copied();
+}
+
 pub struct MousePositionPlugin;
 
 impl Plugin for MousePositionPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MyWorldCoords(Vec2::ZERO))
-            .add_systems(Update, my_cursor_system);
+            .init_resource::<PickedBody>()
+            .add_systems(
+                Update,
+                (my_cursor_system, pick_body_system.after(refresh_query_tree)).chain(),
+            );
     }
 }
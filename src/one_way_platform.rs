@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::{
+    collisions::Collider,
+    contacts::{ContactCache, pair_key},
+    flat_body::FlatBody,
+    helpers::{to_vec2, to_vec3},
+};
+
+/// Cosine of the largest angle to the platform up that still lands on it.
+const LANDING_NORMAL_THRESHOLD: f32 = 0.5;
+
+/// Platform bodies pass through from below and land on from above.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct OneWayPlatform {
+    /// Direction the solid side faces, in the platform local space.
+    pub up: Vec2,
+}
+
+impl Default for OneWayPlatform {
+    fn default() -> Self {
+        return OneWayPlatform { up: Vec2::Y };
+    }
+}
+
+/// Direction the solid side of the platform faces, in world space.
+pub fn platform_up(platform: &OneWayPlatform, platform_transform: &Transform) -> Vec2 {
+    return to_vec2(&(platform_transform.rotation * to_vec3(&platform.up))).normalize_or_zero();
+}
+
+/// Whether a contact with `normal` from the platform lands on it.
+pub fn lands_on(platform: &OneWayPlatform, platform_transform: &Transform, normal: Vec2) -> bool {
+    return normal.dot(platform_up(platform, platform_transform)) >= LANDING_NORMAL_THRESHOLD;
+}
+
+/// Body falls through every one way platform while it has this component.
+#[derive(Component, Default, Debug)]
+pub struct DropThrough;
+
+/// Pass through decisions kept while the pair keeps touching.
+#[derive(Default)]
+pub struct OneWayPlatformPairs {
+    passing: HashMap<(Entity, Entity), bool>,
+}
+
+/// Disables the contacts of every pair passing through a platform.
+pub fn apply_one_way_platforms(
+    query: &Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    platforms: &Query<'_, '_, &OneWayPlatform>,
+    drop_through: &Query<'_, '_, (), With<DropThrough>>,
+    pairs: &mut OneWayPlatformPairs,
+    contacts: &mut ContactCache,
+) {
+    let mut touching = HashSet::new();
+    for contact in contacts.iter_mut() {
+        if contact.is_sensor {
+            continue;
+        }
+        let (platform_entity, body, normal) = if platforms.contains(contact.entity_a) {
+            (contact.entity_a, contact.entity_b, contact.collision_normal)
+        } else if platforms.contains(contact.entity_b) {
+            (
+                contact.entity_b,
+                contact.entity_a,
+                -contact.collision_normal,
+            )
+        } else {
+            continue;
+        };
+
+        let key = pair_key(contact.entity_a, contact.entity_b);
+        touching.insert(key);
+
+        let passing = pairs.passing.entry(key).or_insert_with(|| {
+            let (Ok(platform), Ok((_, platform_transform, _, _))) =
+                (platforms.get(platform_entity), query.get(platform_entity))
+            else {
+                return true;
+            };
+            return !lands_on(platform, platform_transform, normal);
+        });
+        // dropping bodies keep falling after the component is removed
+        if drop_through.contains(body) {
+            *passing = true;
+        }
+
+        if *passing {
+            contact.enabled = false;
+        }
+    }
+
+    pairs.passing.retain(|key, _| touching.contains(key));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::Shape,
+        flat_body::{BoxParams, CircleParams, FlatBodyType, on_flat_body_added},
+        flat_world::run_sub_steps,
+    };
+
+    /// World with a thin platform at the origin and a ball above or below.
+    fn platform_and_ball(y: f32, velocity: f32) -> (World, Entity) {
+        let mut world = World::new();
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(100., 10.))),
+            OneWayPlatform::default(),
+        ));
+        let mut ball = FlatBody::new(1., FlatBodyType::Dynamic, 0.);
+        ball.linear_velocity = Vec2::new(0., velocity);
+        let ball = world
+            .spawn((
+                Transform::from_xyz(0., y, 0.),
+                ball,
+                Collider::new(Shape::Circle(CircleParams::new(5.))),
+            ))
+            .id();
+        return (world, ball);
+    }
+
+    fn height(world: &World, ball: Entity) -> f32 {
+        return world.get::<Transform>(ball).unwrap().translation.y;
+    }
+
+    fn set_velocity(world: &mut World, ball: Entity, velocity: f32) {
+        world.get_mut::<FlatBody>(ball).unwrap().linear_velocity = Vec2::new(0., velocity);
+    }
+
+    #[test]
+    fn passes_up_from_below() {
+        // moves 2 per sub step, 120 in total
+        let (mut world, ball) = platform_and_ball(-30., 120.);
+        run_sub_steps(&mut world, 60, 1. / 60.);
+        let y = height(&world, ball);
+        assert!((y - 90.).abs() < 0.5, "ball ended at {y}");
+    }
+
+    #[test]
+    fn lands_from_above() {
+        let (mut world, ball) = platform_and_ball(30., -120.);
+        run_sub_steps(&mut world, 60, 1. / 60.);
+        let y = height(&world, ball);
+        assert!((y - 10.).abs() < 1., "ball ended at {y}");
+        let velocity = world.get::<FlatBody>(ball).unwrap().linear_velocity;
+        assert!(velocity.y.abs() < 1., "ball still moves at {velocity}");
+    }
+
+    #[test]
+    fn decision_is_kept_halfway_through() {
+        // from below until the center is inside the platform, then back down
+        let (mut world, ball) = platform_and_ball(-12., 120.);
+        run_sub_steps(&mut world, 6, 1. / 60.);
+        assert!(height(&world, ball).abs() < 1.);
+        set_velocity(&mut world, ball, -120.);
+        run_sub_steps(&mut world, 30, 1. / 60.);
+        let y = height(&world, ball);
+        assert!((y + 60.).abs() < 1., "ball ended at {y}");
+
+        // from above the ball stays on top, even when pushed back up
+        let (mut world, ball) = platform_and_ball(30., -120.);
+        run_sub_steps(&mut world, 30, 1. / 60.);
+        set_velocity(&mut world, ball, 60.);
+        run_sub_steps(&mut world, 30, 1. / 60.);
+        assert!((height(&world, ball) - 40.).abs() < 1.);
+    }
+
+    #[test]
+    fn drop_through_falls_from_resting() {
+        let (mut world, ball) = platform_and_ball(30., -120.);
+        run_sub_steps(&mut world, 60, 1. / 60.);
+        assert!((height(&world, ball) - 10.).abs() < 1.);
+
+        world.entity_mut(ball).insert(DropThrough);
+        set_velocity(&mut world, ball, -120.);
+        run_sub_steps(&mut world, 30, 1. / 60.);
+        let y = height(&world, ball);
+        assert!(y < -30., "ball ended at {y}");
+
+        // still passing after the component is removed halfway
+        let (mut world, ball) = platform_and_ball(30., -120.);
+        run_sub_steps(&mut world, 60, 1. / 60.);
+        world.entity_mut(ball).insert(DropThrough);
+        set_velocity(&mut world, ball, -120.);
+        run_sub_steps(&mut world, 5, 1. / 60.);
+        world.entity_mut(ball).remove::<DropThrough>();
+        run_sub_steps(&mut world, 30, 1. / 60.);
+        let y = height(&world, ball);
+        assert!(y < -30., "ball ended at {y}");
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+
+use crate::{
+    collisions::Shape,
+    gjk::{ConvexCore, convex_core, gjk, gjk_collide},
+};
+
+/// Nearest point on the surface of a shape.
+#[derive(Clone, Copy, Debug)]
+pub struct PointProjection {
+    pub point: Vec2,
+    /// Whether the projected point lies inside the shape.
+    pub is_inside: bool,
+}
+
+fn project_point_on_core(core: &ConvexCore, point: Vec2) -> PointProjection {
+    let point_core = ConvexCore {
+        points: vec![point],
+        radius: 0.,
+    };
+    if let Some(details) = gjk_collide(core, &point_core) {
+        // moving the point by the penetration along the normal separates it
+        // from the core, which puts it on the surface
+        return PointProjection {
+            point: point + details.collision_normal * details.penetration_depth,
+            is_inside: true,
+        };
+    }
+
+    let output = gjk(core, &point_core);
+    let direction = (point - output.point_a).normalize_or_zero();
+    return PointProjection {
+        point: output.point_a + direction * core.radius,
+        is_inside: false,
+    };
+}
+
+/// Offset from a piece edge that tells a seam from the outline.
+const SEAM_OFFSET: f32 = 1e-2;
+
+fn core_contains_point(core: &ConvexCore, point: Vec2) -> bool {
+    let point_core = ConvexCore {
+        points: vec![point],
+        radius: 0.,
+    };
+    return gjk(core, &point_core).distance <= core.radius;
+}
+
+fn is_polygon(core: &ConvexCore) -> bool {
+    return core.radius == 0. && core.points.len() >= 3;
+}
+
+/// Edges of a polygon core with their outward normals, for either winding.
+fn polygon_edges(core: &ConvexCore) -> Vec<(Vec2, Vec2, Vec2)> {
+    let points = &core.points;
+    let mut signed_area = 0.;
+    for i in 0..points.len() {
+        signed_area += points[i].perp_dot(points[(i + 1) % points.len()]);
+    }
+
+    let mut edges = Vec::with_capacity(points.len());
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        let normal = (b - a).perp().normalize_or_zero() * -signed_area.signum();
+        edges.push((a, b, normal));
+    }
+    return edges;
+}
+
+/// Range of the segment from `start` to `end` strictly inside the core.
+fn range_inside_polygon(start: Vec2, end: Vec2, core: &ConvexCore) -> Option<(f32, f32)> {
+    let (mut t_min, mut t_max) = (0f32, 1f32);
+    for (a, _b, normal) in polygon_edges(core) {
+        // inside while the distance to the edge line is negative
+        let distance = normal.dot(start - a);
+        let rate = normal.dot(end - start);
+        if rate == 0. {
+            if distance >= 0. {
+                return None;
+            }
+            continue;
+        }
+        let t = -distance / rate;
+        if rate > 0. {
+            t_max = t_max.min(t);
+        } else {
+            t_min = t_min.max(t);
+        }
+    }
+    if t_min >= t_max {
+        return None;
+    }
+    return Some((t_min, t_max));
+}
+
+/// Parts of the edges of polygon piece `index` on the outline of all `cores`.
+fn outline_edges(cores: &[ConvexCore], index: usize) -> Vec<(Vec2, Vec2)> {
+    let mut outline = Vec::new();
+    for (a, b, normal) in polygon_edges(&cores[index]) {
+        let offset = normal * SEAM_OFFSET;
+        let mut hidden: Vec<(f32, f32)> = cores
+            .iter()
+            .enumerate()
+            .filter(|(other, core)| *other != index && is_polygon(core))
+            .filter_map(|(_other, core)| range_inside_polygon(a + offset, b + offset, core))
+            .collect();
+        hidden.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+        let mut visible_from = 0.;
+        for (start, end) in hidden {
+            if start > visible_from {
+                outline.push((a.lerp(b, visible_from), a.lerp(b, start)));
+            }
+            visible_from = end.max(visible_from);
+        }
+        if visible_from < 1. {
+            outline.push((a.lerp(b, visible_from), b));
+        }
+    }
+    return outline;
+}
+
+fn closest_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let edge = b - a;
+    if edge.length_squared() == 0. {
+        return a;
+    }
+    let t = ((point - a).dot(edge) / edge.length_squared()).clamp(0., 1.);
+    return a + edge * t;
+}
+
+/// Nearest point on the outline of several pieces from a point inside them.
+fn project_point_inside_pieces(cores: &[ConvexCore], point: Vec2) -> Option<Vec2> {
+    let mut nearest: Option<Vec2> = None;
+    let mut keep_nearer = |candidate: Vec2| {
+        if nearest.is_none_or(|nearest| {
+            candidate.distance_squared(point) < nearest.distance_squared(point)
+        }) {
+            nearest = Some(candidate);
+        }
+    };
+
+    for (index, core) in cores.iter().enumerate() {
+        if is_polygon(core) {
+            for (a, b) in outline_edges(cores, index) {
+                keep_nearer(closest_on_segment(point, a, b));
+            }
+            continue;
+        }
+
+        let surface = project_point_on_core(core, point).point;
+        let point_core = ConvexCore {
+            points: vec![surface],
+            radius: 0.,
+        };
+        let outward = (surface - gjk(core, &point_core).point_a).normalize_or_zero();
+        let is_seam = cores.iter().enumerate().any(|(other, other_core)| {
+            other != index && core_contains_point(other_core, surface + outward * SEAM_OFFSET)
+        });
+        if !is_seam {
+            keep_nearer(surface);
+        }
+    }
+    return nearest;
+}
+
+/// Nearest surface point of the shape, seams of compounds left out.
+pub fn project_point_on_shape(
+    shape: &Shape,
+    transform: &Transform,
+    point: Vec2,
+) -> Option<PointProjection> {
+    let cores: Vec<ConvexCore> = shape
+        .sub_shapes()
+        .into_iter()
+        .filter_map(|(_index, local, sub_shape)| {
+            convex_core(&sub_shape, &transform.mul_transform(local))
+        })
+        .collect();
+
+    let has_inside = |core: &ConvexCore| is_polygon(core) || core.radius > 0.;
+    if cores.len() > 1
+        && cores
+            .iter()
+            .any(|core| has_inside(core) && core_contains_point(core, point))
+        && let Some(outline_point) = project_point_inside_pieces(&cores, point)
+    {
+        return Some(PointProjection {
+            point: outline_point,
+            is_inside: true,
+        });
+    }
+
+    let mut nearest: Option<PointProjection> = None;
+    for core in cores.iter() {
+        let projection = project_point_on_core(core, point);
+        let is_nearer = match nearest {
+            None => true,
+            Some(nearest) if nearest.is_inside != projection.is_inside => projection.is_inside,
+            Some(nearest) => {
+                projection.point.distance_squared(point) < nearest.point.distance_squared(point)
+            }
+        };
+        if is_nearer {
+            nearest = Some(projection);
+        }
+    }
+    return nearest;
+}
+
+/// Whether `point` lies inside any piece of the shape, edges included.
+pub fn shape_contains_point(shape: &Shape, transform: &Transform, point: Vec2) -> bool {
+    for (_index, local, sub_shape) in shape.sub_shapes() {
+        let Some(core) = convex_core(&sub_shape, &transform.mul_transform(local)) else {
+            continue;
+        };
+        if core_contains_point(&core, point) {
+            return true;
+        }
+    }
+    return false;
+}
@@ -0,0 +1,311 @@
+use bevy::prelude::*;
+
+use crate::{
+    collisions::Shape,
+    gjk::{ConvexCore, convex_core},
+};
+
+/// Where a ray enters a shape, at distance 0 for rays starting inside.
+#[derive(Clone, Copy, Debug)]
+pub struct RayIntersection {
+    pub distance: f32,
+    pub normal: Vec2,
+}
+
+fn closer(
+    best: Option<RayIntersection>,
+    other: Option<RayIntersection>,
+) -> Option<RayIntersection> {
+    match (best, other) {
+        (Some(best), Some(other)) if other.distance < best.distance => Some(other),
+        (None, other) => other,
+        (best, _) => best,
+    }
+}
+
+fn ray_circle(
+    origin: Vec2,
+    direction: Vec2,
+    center: Vec2,
+    radius: f32,
+    max_distance: f32,
+) -> Option<RayIntersection> {
+    let offset = origin - center;
+    let b = offset.dot(direction);
+    let c = offset.length_squared() - radius * radius;
+    if c <= 0. {
+        return Some(RayIntersection {
+            distance: 0.,
+            normal: -direction,
+        });
+    }
+    // outside and pointing away
+    if b > 0. {
+        return None;
+    }
+    let discriminant = b * b - c;
+    if discriminant < 0. {
+        return None;
+    }
+
+    let distance = -b - discriminant.sqrt();
+    if distance > max_distance {
+        return None;
+    }
+    let normal = (origin + direction * distance - center).normalize_or_zero();
+    return Some(RayIntersection { distance, normal });
+}
+
+fn ray_segment(
+    origin: Vec2,
+    direction: Vec2,
+    a: Vec2,
+    b: Vec2,
+    max_distance: f32,
+) -> Option<RayIntersection> {
+    let edge = b - a;
+    let denominator = direction.perp_dot(edge);
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let distance = (a - origin).perp_dot(edge) / denominator;
+    let along_edge = (a - origin).perp_dot(direction) / denominator;
+    if distance < 0. || distance > max_distance || !(0. ..=1.).contains(&along_edge) {
+        return None;
+    }
+
+    let mut normal = edge.perp().normalize_or_zero();
+    if normal.dot(direction) > 0. {
+        normal = -normal;
+    }
+    return Some(RayIntersection { distance, normal });
+}
+
+/// Cyrus-Beck clipping of the ray against every edge of a convex polygon.
+fn ray_polygon(
+    origin: Vec2,
+    direction: Vec2,
+    vertices: &[Vec2],
+    max_distance: f32,
+) -> Option<RayIntersection> {
+    let mut signed_area = 0.;
+    for i in 0..vertices.len() {
+        signed_area += vertices[i].perp_dot(vertices[(i + 1) % vertices.len()]);
+    }
+
+    let mut enter = 0.;
+    let mut exit = max_distance;
+    let mut enter_normal = None;
+    for i in 0..vertices.len() {
+        let v1 = vertices[i];
+        let v2 = vertices[(i + 1) % vertices.len()];
+        let edge = (v2 - v1).normalize_or_zero();
+        let normal = if signed_area >= 0. {
+            Vec2::new(edge.y, -edge.x)
+        } else {
+            Vec2::new(-edge.y, edge.x)
+        };
+
+        // inside is where dot(normal, point - v1) <= 0
+        let numerator = normal.dot(v1 - origin);
+        let denominator = normal.dot(direction);
+        if denominator.abs() < f32::EPSILON {
+            if numerator < 0. {
+                return None;
+            }
+            continue;
+        }
+
+        let distance = numerator / denominator;
+        if denominator < 0. {
+            if distance > enter {
+                enter = distance;
+                enter_normal = Some(normal);
+            }
+        } else if distance < exit {
+            exit = distance;
+        }
+        if enter > exit {
+            return None;
+        }
+    }
+
+    return Some(RayIntersection {
+        distance: enter,
+        normal: enter_normal.unwrap_or(-direction),
+    });
+}
+
+/// Ray against a convex core. `direction` has to be normalized.
+pub fn ray_cast_core(
+    core: &ConvexCore,
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+) -> Option<RayIntersection> {
+    let points = &core.points;
+    if core.radius <= 0. {
+        return match points.len() {
+            0 | 1 => None,
+            2 => ray_segment(origin, direction, points[0], points[1], max_distance),
+            _ => ray_polygon(origin, direction, points, max_distance),
+        };
+    }
+
+    // rounded core is the union of circles around its points and its edges
+    // pushed out to both sides by the radius
+    let mut best = None;
+    for point in points.iter() {
+        let hit = ray_circle(origin, direction, *point, core.radius, max_distance);
+        best = closer(best, hit);
+    }
+    let edge_count = match points.len() {
+        0 | 1 => 0,
+        2 => 1,
+        count => count,
+    };
+    for i in 0..edge_count {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        let side = (b - a).perp().normalize_or_zero() * core.radius;
+        let quad = [a + side, b + side, b - side, a - side];
+        best = closer(best, ray_polygon(origin, direction, &quad, max_distance));
+    }
+    if points.len() > 2 {
+        best = closer(best, ray_polygon(origin, direction, points, max_distance));
+    }
+    return best;
+}
+
+/// First hit of the ray on any piece of the shape, with the index of the piece.
+pub fn ray_cast_shape(
+    shape: &Shape,
+    transform: &Transform,
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+) -> Option<(RayIntersection, usize)> {
+    let mut best: Option<(RayIntersection, usize)> = None;
+    for (index, local, sub_shape) in shape.sub_shapes() {
+        let Some(core) = convex_core(&sub_shape, &transform.mul_transform(local)) else {
+            continue;
+        };
+        let Some(hit) = ray_cast_core(&core, origin, direction, max_distance) else {
+            continue;
+        };
+        if best.is_some_and(|(best_hit, _)| best_hit.distance <= hit.distance) {
+            continue;
+        }
+        best = Some((hit, index));
+    }
+    return best;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flat_body::{BoxParams, CapsuleParams, CircleParams, PolygonParams, SegmentParams};
+
+    fn cast(shape: Shape, origin: Vec2, direction: Vec2) -> Option<RayIntersection> {
+        let transform = Transform::from_xyz(10., 0., 0.);
+        return ray_cast_shape(&shape, &transform, origin, direction, 100.).map(|(hit, _)| hit);
+    }
+
+    fn assert_hit(hit: Option<RayIntersection>, distance: f32, normal: Vec2) {
+        let hit = hit.expect("ray missed");
+        assert!(
+            (hit.distance - distance).abs() < 1e-3,
+            "hit at {}",
+            hit.distance
+        );
+        assert!(hit.normal.distance(normal) < 1e-3, "normal {}", hit.normal);
+    }
+
+    #[test]
+    fn hit_and_miss_per_shape() {
+        // every shape reaches 5 to the left of its center at x = 10, 25 from the origin
+        let shapes = [
+            Shape::Circle(CircleParams::new(5.)),
+            Shape::Box(BoxParams::new(10., 10.)),
+            Shape::Polygon(
+                PolygonParams::new(vec![
+                    Vec2::new(-5., -5.),
+                    Vec2::new(5., 0.),
+                    Vec2::new(-5., 5.),
+                ])
+                .unwrap(),
+            ),
+            Shape::Capsule(CapsuleParams::new(5., 10.)),
+            Shape::Segment(SegmentParams::new(Vec2::new(-5., -5.), Vec2::new(-5., 5.))),
+        ];
+        for shape in shapes {
+            assert_hit(
+                cast(shape.clone(), Vec2::new(-20., 0.), Vec2::X),
+                25.,
+                -Vec2::X,
+            );
+            assert!(cast(shape.clone(), Vec2::new(-20., 20.), Vec2::X).is_none());
+            assert!(cast(shape.clone(), Vec2::new(-20., 0.), -Vec2::X).is_none());
+            // beyond max distance
+            assert!(cast(shape, Vec2::new(-100., 0.), Vec2::X).is_none());
+        }
+    }
+
+    #[test]
+    fn ray_starting_inside() {
+        let direction = Vec2::new(1., 1.).normalize();
+        let shapes = [
+            Shape::Circle(CircleParams::new(5.)),
+            Shape::Box(BoxParams::new(10., 10.)),
+            Shape::Capsule(CapsuleParams::new(5., 10.)),
+        ];
+        for shape in shapes {
+            assert_hit(cast(shape, Vec2::new(11., 1.), direction), 0., -direction);
+        }
+    }
+
+    #[test]
+    fn ray_parallel_to_polygon_edge() {
+        let square = Shape::Box(BoxParams::new(10., 10.));
+        // along the top edge, just below it and just above it
+        assert_hit(
+            cast(square.clone(), Vec2::new(-20., 5.), Vec2::X),
+            25.,
+            -Vec2::X,
+        );
+        assert_hit(
+            cast(square.clone(), Vec2::new(-20., 4.), Vec2::X),
+            25.,
+            -Vec2::X,
+        );
+        assert!(cast(square, Vec2::new(-20., 5.1), Vec2::X).is_none());
+    }
+
+    #[test]
+    fn ray_on_rounded_capsule() {
+        // vertical capsule with its caps centered at (10, -5) and (10, 5)
+        let capsule = Shape::Capsule(CapsuleParams::new(5., 10.));
+        // straight onto the flat side
+        assert_hit(
+            cast(capsule.clone(), Vec2::new(-20., 3.), Vec2::X),
+            25.,
+            -Vec2::X,
+        );
+        // down onto the top cap
+        assert_hit(
+            cast(capsule.clone(), Vec2::new(10., 50.), -Vec2::Y),
+            40.,
+            Vec2::Y,
+        );
+        // onto the side of the top cap, above the flat side
+        let hit = cast(capsule.clone(), Vec2::new(-20., 8.), Vec2::X).unwrap();
+        let point = Vec2::new(-20., 8.) + Vec2::X * hit.distance;
+        assert!((point.distance(Vec2::new(10., 5.)) - 5.).abs() < 1e-3);
+        assert!(hit.normal.x < 0. && hit.normal.y > 0.);
+        // through the corner of the bounding box, past the top cap
+        let direction = Vec2::new(1., 1.).normalize();
+        let origin = Vec2::new(5.5, 9.5) - direction * 20.;
+        assert!(cast(capsule, origin, direction).is_none());
+    }
+}
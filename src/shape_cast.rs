@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+
+use crate::{
+    ccd::{time_of_impact, touching_normal},
+    collisions::Shape,
+    gjk::closest_points,
+};
+
+/// Where a moving shape first touches another one.
+#[derive(Clone, Copy, Debug)]
+pub struct ShapeCastIntersection {
+    /// Distance travelled along the cast direction before the hit.
+    pub distance: f32,
+    /// Contact point on the surface of the other shape.
+    pub point: Vec2,
+    /// Surface normal of the other shape, pointing towards the moving one.
+    pub normal: Vec2,
+}
+
+/// First hit of `shape` moved along the normalized `direction`, with the piece index.
+pub fn cast_shape(
+    shape: &Shape,
+    start: &Transform,
+    direction: Vec2,
+    max_distance: f32,
+    other_shape: &Shape,
+    other_transform: &Transform,
+) -> Option<(ShapeCastIntersection, usize)> {
+    let mut best: Option<(ShapeCastIntersection, usize)> = None;
+    for (other_index, other_local, other_sub_shape) in other_shape.sub_shapes() {
+        let other_transform = other_transform.mul_transform(other_local);
+        let hit = if let Some(normal) =
+            touching_normal(shape, start, &other_sub_shape, &other_transform)
+        {
+            // moving away or along the surface
+            if direction.dot(normal) <= 0. {
+                continue;
+            }
+            let Some(closest) = closest_points(shape, start, &other_sub_shape, &other_transform)
+            else {
+                continue;
+            };
+            ShapeCastIntersection {
+                distance: 0.,
+                point: closest.point_b,
+                normal: -normal,
+            }
+        } else {
+            let Some(impact) = time_of_impact(
+                shape,
+                start,
+                direction * max_distance,
+                0.,
+                &other_sub_shape,
+                &other_transform,
+            ) else {
+                continue;
+            };
+            ShapeCastIntersection {
+                distance: impact.time * max_distance,
+                point: impact.point,
+                normal: -impact.normal,
+            }
+        };
+        if best.is_some_and(|(best_hit, _)| best_hit.distance <= hit.distance) {
+            continue;
+        }
+        best = Some((hit, other_index));
+    }
+    return best;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flat_body::{BoxParams, CircleParams};
+
+    /// Casts a circle of radius 5 onto a 100 by 20 box at the origin.
+    fn cast_onto_box(
+        start: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+    ) -> Option<ShapeCastIntersection> {
+        return cast_shape(
+            &Shape::Circle(CircleParams::new(5.)),
+            &Transform::from_translation(start.extend(0.)),
+            direction,
+            max_distance,
+            &Shape::Box(BoxParams::new(100., 20.)),
+            &Transform::IDENTITY,
+        )
+        .map(|(hit, _)| hit);
+    }
+
+    #[test]
+    fn circle_cast_onto_box() {
+        let hit = cast_onto_box(Vec2::new(0., 50.), Vec2::NEG_Y, 100.).unwrap();
+        // left just short of touching
+        assert!(
+            hit.distance <= 35. && hit.distance > 34.7,
+            "{}",
+            hit.distance
+        );
+        assert!(hit.normal.distance(Vec2::Y) < 1e-3, "{}", hit.normal);
+        assert!(
+            hit.point.distance(Vec2::new(0., 10.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+
+        // diagonally onto the corner
+        let direction = Vec2::new(-1., -1.).normalize();
+        let hit = cast_onto_box(Vec2::new(80., 40.), direction, 100.).unwrap();
+        assert!(
+            hit.point.distance(Vec2::new(50., 10.)) < 0.1,
+            "{}",
+            hit.point
+        );
+    }
+
+    #[test]
+    fn circle_cast_misses() {
+        assert!(cast_onto_box(Vec2::new(0., 50.), Vec2::X, 100.).is_none());
+        assert!(cast_onto_box(Vec2::new(0., 50.), Vec2::Y, 100.).is_none());
+        assert!(cast_onto_box(Vec2::new(80., 50.), Vec2::NEG_Y, 100.).is_none());
+    }
+
+    #[test]
+    fn overlapping_start_only_hits_moving_in() {
+        // 3 deep into the top of the box
+        let start = Vec2::new(0., 12.);
+        assert!(cast_onto_box(start, Vec2::Y, 100.).is_none());
+        assert!(cast_onto_box(start, Vec2::X, 100.).is_none());
+        let hit = cast_onto_box(start, Vec2::NEG_Y, 100.).unwrap();
+        assert_eq!(hit.distance, 0.);
+        assert!(hit.normal.distance(Vec2::Y) < 1e-3, "{}", hit.normal);
+    }
+
+    #[test]
+    fn grazing_cast_hits() {
+        // sinks 10 over 200 towards the top of a long box
+        let circle = Shape::Circle(CircleParams::new(5.));
+        let start = Transform::from_xyz(-200., 25., 0.);
+        let long_box = Shape::Box(BoxParams::new(2000., 20.));
+        let direction = Vec2::new(1., -0.05).normalize();
+        for max_distance in [1000., 250.] {
+            let (hit, _) = cast_shape(
+                &circle,
+                &start,
+                direction,
+                max_distance,
+                &long_box,
+                &Transform::IDENTITY,
+            )
+            .unwrap();
+            assert!(
+                (hit.distance - 200.).abs() < 6.,
+                "{max_distance}: {}",
+                hit.distance
+            );
+            assert!(hit.normal.distance(Vec2::Y) < 1e-3, "{}", hit.normal);
+        }
+    }
+
+    #[test]
+    fn max_distance_cuts_off_the_cast() {
+        assert!(cast_onto_box(Vec2::new(0., 50.), Vec2::NEG_Y, 30.).is_none());
+        assert!(cast_onto_box(Vec2::new(0., 50.), Vec2::NEG_Y, 36.).is_some());
+    }
+}
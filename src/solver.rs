@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+
+use bevy::{math::FloatPow, prelude::*};
+
+use crate::{
+    collisions::Collider,
+    contacts::ContactCache,
+    flat_body::FlatBody,
+    helpers::{to_vec2, to_vec3},
+};
+
+/// Contacts approaching slower than this do not bounce.
+const RESTITUTION_VELOCITY_THRESHOLD: f32 = 20.;
+/// Contact points slipping slower than this use static friction.
+const STICKING_VELOCITY_THRESHOLD: f32 = 10.;
+
+/// How penetration left after the collision step is removed.
+#[derive(Clone, Copy, Debug)]
+pub enum PositionCorrection {
+    /// Bias in the normal velocity, cheapest but adds energy.
+    Baumgarte,
+    /// Bias solved with pseudo velocities which are thrown away.
+    SplitImpulse,
+    /// Position pass after the velocity solve, most accurate.
+    NonLinearGaussSeidel,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SolverSettings {
+    /// Solver passes over all contacts in every sub step.
+    pub velocity_iterations: u32,
+    /// Start the solver from the impulses of the previous step.
+    pub warm_starting: bool,
+    pub position_correction: PositionCorrection,
+    /// Passes of `PositionCorrection::NonLinearGaussSeidel`.
+    pub position_iterations: u32,
+    /// Part of the penetration removed in one sub step, between 0 and 1.
+    pub correction_factor: f32,
+    /// Penetration which is left alone so resting contacts stay touching.
+    pub linear_slop: f32,
+    /// Largest position change of a contact in one position pass.
+    pub max_correction: f32,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        SolverSettings {
+            velocity_iterations: 8,
+            warm_starting: true,
+            position_correction: PositionCorrection::NonLinearGaussSeidel,
+            position_iterations: 3,
+            correction_factor: 0.2,
+            linear_slop: 0.5,
+            max_correction: 5.,
+        }
+    }
+}
+
+struct ConstraintPoint {
+    ra: Vec2,
+    rb: Vec2,
+    /// Deepest points of A and B in their local space, for position passes.
+    local_anchor_a: Vec2,
+    local_anchor_b: Vec2,
+    depth: f32,
+    normal_mass: f32,
+    tangent_mass: f32,
+    velocity_bias: f32,
+    /// Static or dynamic friction, chosen once per sub step.
+    friction: f32,
+    normal_impulse: f32,
+    tangent_impulse: f32,
+    split_impulse: f32,
+}
+
+struct ContactConstraint {
+    manifold_index: usize,
+    entity_a: Entity,
+    entity_b: Entity,
+    normal: Vec2,
+    tangent: Vec2,
+    tangent_speed: f32,
+    points: Vec<ConstraintPoint>,
+}
+
+fn point_velocity(linear_velocity: Vec2, angular_velocity: f32, r: Vec2) -> Vec2 {
+    return linear_velocity + Vec2::new(-r.y, r.x) * angular_velocity;
+}
+
+fn apply_impulse(
+    body_a: &mut FlatBody,
+    body_b: &mut FlatBody,
+    point: &ConstraintPoint,
+    impulse: Vec2,
+) {
+    body_a.linear_velocity -= impulse * *body_a.inv_mass();
+    body_a.angular_velocity -= point.ra.perp_dot(impulse) * body_a.inv_inertia();
+    body_b.linear_velocity += impulse * *body_b.inv_mass();
+    body_b.angular_velocity += point.rb.perp_dot(impulse) * body_b.inv_inertia();
+}
+
+fn effective_mass(body_a: &FlatBody, body_b: &FlatBody, ra: Vec2, rb: Vec2, axis: Vec2) -> f32 {
+    let ra_cross = ra.perp_dot(axis);
+    let rb_cross = rb.perp_dot(axis);
+    let k = body_a.inv_mass()
+        + body_b.inv_mass()
+        + ra_cross.squared() * body_a.inv_inertia()
+        + rb_cross.squared() * body_b.inv_inertia();
+    if k > 0. {
+        return 1. / k;
+    }
+    return 0.;
+}
+
+fn to_local(transform: &Transform, point: Vec2) -> Vec2 {
+    let offset = point - to_vec2(&transform.translation);
+    return to_vec2(&(transform.rotation.inverse() * to_vec3(&offset)));
+}
+
+fn to_global(transform: &Transform, local_point: Vec2) -> Vec2 {
+    return to_vec2(&(transform.translation + transform.rotation * to_vec3(&local_point)));
+}
+
+fn move_body(transform: &mut Mut<Transform>, translation: Vec2, rotation: f32) {
+    // static bodies get nothing and must stay unchanged to keep their cached AABB
+    if translation == Vec2::ZERO && rotation == 0. {
+        return;
+    }
+    transform.translation += to_vec3(&translation);
+    transform.rotate_z(rotation);
+}
+
+/// Sequential impulse solver with accumulated impulses.
+pub fn solve_contacts(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    contacts: &mut ContactCache,
+    settings: &SolverSettings,
+    delta_time: f32,
+) {
+    let mut manifolds: Vec<_> = contacts
+        .iter_mut()
+        .filter(|manifold| manifold.enabled && !manifold.is_sensor)
+        .collect();
+    let mut constraints: Vec<ContactConstraint> = Vec::with_capacity(manifolds.len());
+
+    for (manifold_index, manifold) in manifolds.iter().enumerate() {
+        let Ok(
+            [
+                (_entity_a, transform_a, body_a, _collider_a),
+                (_entity_b, transform_b, body_b, _collider_b),
+            ],
+        ) = query.get_many([manifold.entity_a, manifold.entity_b])
+        else {
+            continue;
+        };
+
+        let normal = manifold.collision_normal;
+        let tangent = Vec2::new(-normal.y, normal.x);
+        let restitution = manifold.restitution;
+
+        let points = manifold
+            .contact_points
+            .iter()
+            .map(|contact_point| {
+                let ra = contact_point.position - to_vec2(&transform_a.translation);
+                let rb = contact_point.position - to_vec2(&transform_b.translation);
+
+                let relative_velocity =
+                    point_velocity(body_b.linear_velocity, body_b.angular_velocity, rb)
+                        - point_velocity(body_a.linear_velocity, body_a.angular_velocity, ra);
+                let normal_velocity = relative_velocity.dot(normal);
+                // the limit stays the same for all iterations, switching it in
+                // between makes the friction impulse jump back and forth
+                let tangent_velocity = relative_velocity.dot(tangent) - manifold.tangent_speed;
+                let friction = if tangent_velocity.abs() < STICKING_VELOCITY_THRESHOLD {
+                    manifold.static_friction
+                } else {
+                    manifold.dynamic_friction
+                };
+                let mut velocity_bias = if contact_point.depth < 0. {
+                    // speculative contact, only the speed closing the gap is allowed
+                    contact_point.depth / delta_time
+                } else if normal_velocity < -RESTITUTION_VELOCITY_THRESHOLD {
+                    -restitution * normal_velocity
+                } else {
+                    0.
+                };
+                if let PositionCorrection::Baumgarte = settings.position_correction
+                    && contact_point.depth > settings.linear_slop
+                {
+                    let correction = (contact_point.depth - settings.linear_slop).max(0.);
+                    velocity_bias =
+                        velocity_bias.max(settings.correction_factor * correction / delta_time);
+                }
+
+                let (normal_impulse, tangent_impulse) = if settings.warm_starting {
+                    (contact_point.normal_impulse, contact_point.tangent_impulse)
+                } else {
+                    (0., 0.)
+                };
+
+                // contact point sits midway, the deepest points are half the depth away
+                let half_depth = normal * contact_point.depth / 2.;
+
+                ConstraintPoint {
+                    ra,
+                    rb,
+                    local_anchor_a: to_local(&transform_a, contact_point.position + half_depth),
+                    local_anchor_b: to_local(&transform_b, contact_point.position - half_depth),
+                    depth: contact_point.depth,
+                    normal_mass: effective_mass(&body_a, &body_b, ra, rb, normal),
+                    tangent_mass: effective_mass(&body_a, &body_b, ra, rb, tangent),
+                    velocity_bias,
+                    friction,
+                    normal_impulse,
+                    tangent_impulse,
+                    split_impulse: 0.,
+                }
+            })
+            .collect();
+
+        constraints.push(ContactConstraint {
+            manifold_index,
+            entity_a: manifold.entity_a,
+            entity_b: manifold.entity_b,
+            normal,
+            tangent,
+            tangent_speed: manifold.tangent_speed,
+            points,
+        });
+    }
+
+    if settings.warm_starting {
+        for constraint in constraints.iter() {
+            let Ok([(_, _, mut body_a, _), (_, _, mut body_b, _)]) =
+                query.get_many_mut([constraint.entity_a, constraint.entity_b])
+            else {
+                continue;
+            };
+            for point in constraint.points.iter() {
+                let impulse = point.normal_impulse * constraint.normal
+                    + point.tangent_impulse * constraint.tangent;
+                apply_impulse(&mut body_a, &mut body_b, point, impulse);
+            }
+        }
+    }
+
+    for _iteration in 0..settings.velocity_iterations {
+        for constraint in constraints.iter_mut() {
+            let Ok([(_, _, mut body_a, _), (_, _, mut body_b, _)]) =
+                query.get_many_mut([constraint.entity_a, constraint.entity_b])
+            else {
+                continue;
+            };
+
+            // friction first, normal impulse is more important and goes last
+            for point in constraint.points.iter_mut() {
+                let relative_velocity =
+                    point_velocity(body_b.linear_velocity, body_b.angular_velocity, point.rb)
+                        - point_velocity(body_a.linear_velocity, body_a.angular_velocity, point.ra);
+                let tangent_velocity =
+                    relative_velocity.dot(constraint.tangent) - constraint.tangent_speed;
+
+                let lambda = -point.tangent_mass * tangent_velocity;
+                let max_friction = point.friction * point.normal_impulse;
+                let new_impulse =
+                    (point.tangent_impulse + lambda).clamp(-max_friction, max_friction);
+                let lambda = new_impulse - point.tangent_impulse;
+                point.tangent_impulse = new_impulse;
+
+                apply_impulse(&mut body_a, &mut body_b, point, lambda * constraint.tangent);
+            }
+
+            for point in constraint.points.iter_mut() {
+                let relative_velocity =
+                    point_velocity(body_b.linear_velocity, body_b.angular_velocity, point.rb)
+                        - point_velocity(body_a.linear_velocity, body_a.angular_velocity, point.ra);
+                let normal_velocity = relative_velocity.dot(constraint.normal);
+
+                let lambda = -point.normal_mass * (normal_velocity - point.velocity_bias);
+                let new_impulse = (point.normal_impulse + lambda).max(0.);
+                let lambda = new_impulse - point.normal_impulse;
+                point.normal_impulse = new_impulse;
+
+                apply_impulse(&mut body_a, &mut body_b, point, lambda * constraint.normal);
+            }
+        }
+    }
+
+    for constraint in constraints.iter() {
+        for (contact_point, point) in manifolds[constraint.manifold_index]
+            .contact_points
+            .iter_mut()
+            .zip(constraint.points.iter())
+        {
+            contact_point.normal_impulse = point.normal_impulse;
+            contact_point.tangent_impulse = point.tangent_impulse;
+        }
+    }
+
+    match settings.position_correction {
+        PositionCorrection::Baumgarte => {}
+        PositionCorrection::SplitImpulse => {
+            solve_split_impulses(query, &mut constraints, settings, delta_time)
+        }
+        PositionCorrection::NonLinearGaussSeidel => solve_positions(query, &constraints, settings),
+    }
+}
+
+/// Pushes penetrating bodies apart with pseudo velocities.
+fn solve_split_impulses(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    constraints: &mut [ContactConstraint],
+    settings: &SolverSettings,
+    delta_time: f32,
+) {
+    let mut pseudo_velocities: HashMap<Entity, (Vec2, f32)> = HashMap::new();
+
+    for _iteration in 0..settings.velocity_iterations {
+        for constraint in constraints.iter_mut() {
+            let Ok([(_, _, body_a, _), (_, _, body_b, _)]) =
+                query.get_many([constraint.entity_a, constraint.entity_b])
+            else {
+                continue;
+            };
+
+            for point in constraint.points.iter_mut() {
+                let (linear_a, angular_a) = pseudo_velocities
+                    .get(&constraint.entity_a)
+                    .copied()
+                    .unwrap_or_default();
+                let (linear_b, angular_b) = pseudo_velocities
+                    .get(&constraint.entity_b)
+                    .copied()
+                    .unwrap_or_default();
+
+                let normal_velocity = (point_velocity(linear_b, angular_b, point.rb)
+                    - point_velocity(linear_a, angular_a, point.ra))
+                .dot(constraint.normal);
+                let bias = settings.correction_factor
+                    * (point.depth - settings.linear_slop).max(0.)
+                    / delta_time;
+
+                let lambda = -point.normal_mass * (normal_velocity - bias);
+                let new_impulse = (point.split_impulse + lambda).max(0.);
+                let impulse = (new_impulse - point.split_impulse) * constraint.normal;
+                point.split_impulse = new_impulse;
+
+                pseudo_velocities.insert(
+                    constraint.entity_a,
+                    (
+                        linear_a - impulse * *body_a.inv_mass(),
+                        angular_a - point.ra.perp_dot(impulse) * body_a.inv_inertia(),
+                    ),
+                );
+                pseudo_velocities.insert(
+                    constraint.entity_b,
+                    (
+                        linear_b + impulse * *body_b.inv_mass(),
+                        angular_b + point.rb.perp_dot(impulse) * body_b.inv_inertia(),
+                    ),
+                );
+            }
+        }
+    }
+
+    for (entity, (linear, angular)) in pseudo_velocities {
+        let Ok((_, mut transform, _, _)) = query.get_mut(entity) else {
+            continue;
+        };
+        move_body(&mut transform, linear * delta_time, angular * delta_time);
+    }
+}
+
+/// Non linear Gauss-Seidel position pass.
+fn solve_positions(
+    query: &mut Query<'_, '_, (Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+    constraints: &[ContactConstraint],
+    settings: &SolverSettings,
+) {
+    for _iteration in 0..settings.position_iterations {
+        for constraint in constraints.iter() {
+            let Ok(
+                [
+                    (_, mut transform_a, body_a, _),
+                    (_, mut transform_b, body_b, _),
+                ],
+            ) = query.get_many_mut([constraint.entity_a, constraint.entity_b])
+            else {
+                continue;
+            };
+
+            for point in constraint.points.iter() {
+                let anchor_a = to_global(&transform_a, point.local_anchor_a);
+                let anchor_b = to_global(&transform_b, point.local_anchor_b);
+                let separation = (anchor_b - anchor_a).dot(constraint.normal);
+
+                let correction = (settings.correction_factor * (separation + settings.linear_slop))
+                    .clamp(-settings.max_correction, 0.);
+                if correction == 0. {
+                    continue;
+                }
+
+                let position = (anchor_a + anchor_b) / 2.;
+                let ra = position - to_vec2(&transform_a.translation);
+                let rb = position - to_vec2(&transform_b.translation);
+                let mass = effective_mass(&body_a, &body_b, ra, rb, constraint.normal);
+                let impulse = -correction * mass * constraint.normal;
+
+                move_body(
+                    &mut transform_a,
+                    -impulse * *body_a.inv_mass(),
+                    -ra.perp_dot(impulse) * body_a.inv_inertia(),
+                );
+                move_body(
+                    &mut transform_b,
+                    impulse * *body_b.inv_mass(),
+                    rb.perp_dot(impulse) * body_b.inv_inertia(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collisions::Shape,
+        flat_body::{BoxParams, FlatBodyType, on_flat_body_added},
+        flat_world::{FlatWorld, run_sub_steps},
+    };
+
+    /// Final height and vertical velocity of a box sunk into the ground.
+    fn push_out_sunken_box(position_correction: PositionCorrection) -> (f32, f32) {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld {
+            solver: SolverSettings {
+                position_correction,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        world.add_observer(on_flat_body_added);
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(400., 100.))),
+        ));
+        let body = world
+            .spawn((
+                Transform::from_xyz(0., 65., 0.),
+                FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                Collider::new(Shape::Box(BoxParams::new(50., 50.))),
+            ))
+            .id();
+
+        run_sub_steps(&mut world, 60, 1. / 60.);
+
+        let height = world.get::<Transform>(body).unwrap().translation.y;
+        let velocity = world.get::<FlatBody>(body).unwrap().linear_velocity.y;
+        return (height, velocity);
+    }
+
+    #[test]
+    fn every_position_correction_pushes_out() {
+        for position_correction in [
+            PositionCorrection::Baumgarte,
+            PositionCorrection::SplitImpulse,
+            PositionCorrection::NonLinearGaussSeidel,
+        ] {
+            let (height, velocity) = push_out_sunken_box(position_correction);
+            // resting height is 75, slop may be left
+            assert!(height > 74., "{position_correction:?} height {height}");
+
+            match position_correction {
+                // the bias stays in the velocity, but never more than the
+                // bias of the starting penetration
+                PositionCorrection::Baumgarte => {
+                    let settings = SolverSettings::default();
+                    let max_bias = settings.correction_factor * (10. - settings.linear_slop) * 60.;
+                    assert!(
+                        velocity.abs() <= max_bias + 1e-2,
+                        "{position_correction:?} velocity {velocity}"
+                    );
+                }
+                _ => {
+                    assert!(
+                        velocity.abs() < 1e-3,
+                        "{position_correction:?} velocity {velocity}"
+                    );
+                    assert!(height < 76., "{position_correction:?} height {height}");
+                }
+            }
+        }
+    }
+
+    fn spawn_box(world: &mut World, transform: Transform, size: Vec2, body: FlatBody) -> Entity {
+        return world
+            .spawn((
+                transform,
+                body,
+                Collider::new(Shape::Box(BoxParams::new(size.x, size.y))),
+            ))
+            .id();
+    }
+
+    fn gravity_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld {
+            gravity: Vec2::new(0., -300.),
+            ..Default::default()
+        });
+        world.add_observer(on_flat_body_added);
+        return world;
+    }
+
+    /// Sub step length of the app, 6 sub steps of a 64 Hz fixed step.
+    const SUB_STEP: f32 = 1. / 384.;
+
+    #[test]
+    fn stack_of_ten_boxes_comes_to_rest() {
+        let mut world = gravity_world();
+        spawn_box(
+            &mut world,
+            Transform::default(),
+            Vec2::new(400., 100.),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+        );
+        let boxes: Vec<Entity> = (0..10)
+            .map(|i| {
+                spawn_box(
+                    &mut world,
+                    Transform::from_xyz(0., 70. + 40. * i as f32, 0.),
+                    Vec2::new(40., 40.),
+                    FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                )
+            })
+            .collect();
+
+        // three seconds
+        run_sub_steps(&mut world, 3 * 384, SUB_STEP);
+
+        for (i, entity) in boxes.iter().enumerate() {
+            let transform = world.get::<Transform>(*entity).unwrap();
+            let flat_body = world.get::<FlatBody>(*entity).unwrap();
+            let resting_height = 70. + 40. * i as f32;
+            assert!(
+                flat_body.linear_velocity.length() < 1.,
+                "box {i} velocity {}",
+                flat_body.linear_velocity
+            );
+            assert!(
+                flat_body.angular_velocity.abs() < 0.01,
+                "box {i} angular velocity {}",
+                flat_body.angular_velocity
+            );
+            assert!(
+                transform.translation.x.abs() < 0.5,
+                "box {i} drifted to {}",
+                transform.translation
+            );
+            // every contact below the box may keep about the slop
+            let slop = SolverSettings::default().linear_slop + 0.1;
+            assert!(
+                (transform.translation.y - resting_height).abs() < slop * (i + 1) as f32,
+                "box {i} at {}",
+                transform.translation
+            );
+        }
+    }
+
+    #[test]
+    fn sliding_box_stops_with_dynamic_friction() {
+        let mut world = gravity_world();
+        spawn_box(
+            &mut world,
+            Transform::default(),
+            Vec2::new(2000., 100.),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+        );
+        let mut sliding = FlatBody::new(1., FlatBodyType::Dynamic, 0.);
+        sliding.linear_velocity = Vec2::new(200., 0.);
+        let body = spawn_box(
+            &mut world,
+            Transform::from_xyz(0., 70., 0.),
+            Vec2::new(40., 40.),
+            sliding,
+        );
+
+        run_sub_steps(&mut world, 3 * 384, SUB_STEP);
+
+        // dynamic friction 0.4 slows it by 120 per second, it slides
+        // 200^2 / (2 * 120) before it sticks
+        let position = world.get::<Transform>(body).unwrap().translation;
+        let velocity = world.get::<FlatBody>(body).unwrap().linear_velocity;
+        assert!(velocity.length() < 1e-2, "velocity {velocity}");
+        assert!((position.x - 166.7).abs() < 5., "position {position}");
+    }
+
+    #[test]
+    fn box_sticks_on_gentle_slope() {
+        let mut world = gravity_world();
+        // 20 degrees is below the static friction angle of 0.6 but above the
+        // dynamic one of 0.4, a body switching to dynamic friction would creep
+        let angle = 20f32.to_radians();
+        let slope = Transform::from_rotation(Quat::from_rotation_z(angle));
+        spawn_box(
+            &mut world,
+            slope,
+            Vec2::new(1000., 100.),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+        );
+        let normal = Vec2::from_angle(angle).perp();
+        let start = normal * 70.;
+        let body = spawn_box(
+            &mut world,
+            slope.with_translation(start.extend(0.)),
+            Vec2::new(40., 40.),
+            FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+        );
+
+        run_sub_steps(&mut world, 2 * 384, SUB_STEP);
+
+        let position = world.get::<Transform>(body).unwrap().translation.truncate();
+        let velocity = world.get::<FlatBody>(body).unwrap().linear_velocity;
+        assert!(position.distance(start) < 1., "moved to {position}");
+        assert!(velocity.length() < 1., "velocity {velocity}");
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::flat_aabb::FlatAABB;
+
+/// Bodies covering more cells are tested against every other body instead.
+const MAX_CELLS_PER_BODY: i64 = 64;
+
+/// Uniform grid broad phase.
+pub struct SpatialHash {
+    pub cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    oversized: Vec<usize>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        return SpatialHash {
+            cell_size,
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+        };
+    }
+
+    /// Min and max cell coordinates covered by the AABB.
+    fn cell_range(&self, aabb: &FlatAABB) -> ((i32, i32), (i32, i32)) {
+        let min = (aabb.min / self.cell_size).floor();
+        let max = (aabb.max / self.cell_size).floor();
+        return ((min.x as i32, min.y as i32), (max.x as i32, max.y as i32));
+    }
+
+    /// Sorted pairs of indices into `aabbs` that share a cell.
+    pub fn candidate_pairs(&mut self, aabbs: &[FlatAABB]) -> Vec<(usize, usize)> {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+        self.oversized.clear();
+
+        let ranges: Vec<_> = aabbs.iter().map(|aabb| self.cell_range(aabb)).collect();
+        for (index, ((min_x, min_y), (max_x, max_y))) in ranges.iter().enumerate() {
+            let cell_count =
+                (*max_x as i64 - *min_x as i64 + 1) * (*max_y as i64 - *min_y as i64 + 1);
+            if cell_count > MAX_CELLS_PER_BODY {
+                self.oversized.push(index);
+                continue;
+            }
+            for x in *min_x..=*max_x {
+                for y in *min_y..=*max_y {
+                    self.cells.entry((x, y)).or_default().push(index);
+                }
+            }
+        }
+        // cells which stayed empty for a step are dropped so the map does not grow forever
+        self.cells.retain(|_, cell| !cell.is_empty());
+
+        let mut pairs = Vec::new();
+        for (&(x, y), cell) in self.cells.iter() {
+            for (i, &a) in cell.iter().enumerate() {
+                for &b in cell[i + 1..].iter() {
+                    // pairs sharing several cells are only reported from the
+                    // first shared cell, which is the max of both min cells
+                    let ((min_ax, min_ay), _) = ranges[a];
+                    let ((min_bx, min_by), _) = ranges[b];
+                    if (x, y) != (min_ax.max(min_bx), min_ay.max(min_by)) {
+                        continue;
+                    }
+                    pairs.push((a.min(b), a.max(b)));
+                }
+            }
+        }
+
+        for (i, &a) in self.oversized.iter().enumerate() {
+            for b in 0..aabbs.len() {
+                // oversized pairs among themselves are added once from the lower one
+                if self.oversized[..=i].contains(&b) {
+                    continue;
+                }
+                pairs.push((a.min(b), a.max(b)));
+            }
+        }
+
+        pairs.sort_unstable();
+        return pairs;
+    }
+}
+
+impl Default for SpatialHash {
+    fn default() -> Self {
+        return SpatialHash::new(100.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_sharing_a_cell() {
+        let aabbs = [
+            FlatAABB::new(10., 10., 40., 40.),
+            // cells (0, 0) and (1, 0)
+            FlatAABB::new(50., 50., 150., 90.),
+            FlatAABB::new(120., 10., 130., 20.),
+            FlatAABB::new(500., 500., 510., 510.),
+            // oversized, paired with everything
+            FlatAABB::new(-1000., -1000., 1000., 1000.),
+            // share four cells, reported once
+            FlatAABB::new(50., 150., 150., 250.),
+            FlatAABB::new(60., 160., 160., 260.),
+        ];
+        let expected = [
+            (0, 1),
+            (0, 4),
+            (1, 2),
+            (1, 4),
+            (2, 4),
+            (3, 4),
+            (4, 5),
+            (4, 6),
+            (5, 6),
+        ];
+
+        let mut spatial_hash = SpatialHash::new(100.);
+        assert_eq!(spatial_hash.candidate_pairs(&aabbs), expected);
+        // cells of the previous call are cleared
+        assert_eq!(spatial_hash.candidate_pairs(&aabbs), expected);
+        assert_eq!(spatial_hash.candidate_pairs(&aabbs[..4]), [(0, 1), (1, 2)]);
+    }
+}
@@ -0,0 +1,832 @@
+use std::collections::HashSet;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    collision_layers::CollisionLayers,
+    collisions::{Collider, Shape},
+    flat_aabb::FlatAABB,
+    flat_body::BoxParams,
+    flat_world::FlatWorld,
+    gjk::{ClosestPoints, closest_points, shape_distance},
+    point_query::{project_point_on_shape, shape_contains_point},
+    ray_cast::ray_cast_shape,
+    shape_cast::cast_shape,
+};
+
+/// Half size of the first box searched by `project_point`.
+const PROJECT_POINT_SEARCH_SIZE: f32 = 32.;
+/// How often `project_point` doubles its search box.
+const MAX_PROJECT_POINT_SEARCHES: usize = 24;
+
+/// Decides which colliders spatial queries can find.
+#[derive(Clone, Debug)]
+pub struct SpatialQueryFilter {
+    /// Only colliders that are members of one of these layers are found.
+    pub mask: u32,
+    pub excluded_entities: HashSet<Entity>,
+    /// Sensors are left out unless this is set.
+    pub include_sensors: bool,
+}
+
+impl Default for SpatialQueryFilter {
+    fn default() -> Self {
+        return SpatialQueryFilter {
+            mask: u32::MAX,
+            excluded_entities: HashSet::new(),
+            include_sensors: false,
+        };
+    }
+}
+
+impl SpatialQueryFilter {
+    pub fn from_mask(mask: u32) -> Self {
+        return SpatialQueryFilter {
+            mask,
+            ..Default::default()
+        };
+    }
+
+    pub fn with_excluded_entities(mut self, entities: impl IntoIterator<Item = Entity>) -> Self {
+        self.excluded_entities.extend(entities);
+        return self;
+    }
+
+    fn accepts(
+        &self,
+        entity: Entity,
+        collider: &Collider,
+        layers: Option<&CollisionLayers>,
+    ) -> bool {
+        if self.excluded_entities.contains(&entity) {
+            return false;
+        }
+        if collider.sensor && !self.include_sensors {
+            return false;
+        }
+        let memberships = layers.copied().unwrap_or_default().memberships;
+        return memberships & self.mask != 0;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub entity: Entity,
+    /// Distance along the ray, 0 when the ray starts inside the collider.
+    pub distance: f32,
+    pub point: Vec2,
+    pub normal: Vec2,
+    /// Piece of a chain or compound collider that was hit.
+    pub sub_shape: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShapeHit {
+    pub entity: Entity,
+    /// Distance the shape can move before touching the collider.
+    pub distance: f32,
+    /// Contact point on the surface of the hit collider.
+    pub point: Vec2,
+    /// Surface normal of the hit collider, pointing towards the cast shape.
+    pub normal: Vec2,
+    /// Piece of a chain or compound collider that was hit.
+    pub sub_shape: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointProjectionHit {
+    pub entity: Entity,
+    /// Nearest point on the surface of the collider.
+    pub point: Vec2,
+    /// Whether the projected point lies inside the collider.
+    pub is_inside: bool,
+}
+
+/// Queries against the colliders of the world, for use in gameplay systems.
+/// Candidates come from `FlatWorld::query_tree`, systems using it run after
+/// `refresh_query_tree` to see bodies moved since the last `world_step`.
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    colliders: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static Transform,
+            &'static Collider,
+            Option<&'static CollisionLayers>,
+        ),
+    >,
+    flat_world: Res<'w, FlatWorld>,
+}
+
+impl SpatialQuery<'_, '_> {
+    /// Entities accepted by `filter` whose AABB overlaps `aabb`.
+    fn candidates(&self, aabb: &FlatAABB, filter: &SpatialQueryFilter) -> Vec<Entity> {
+        let mut entities = Vec::new();
+        self.flat_world
+            .query_tree
+            .query(aabb, |entity, _tight_aabb| entities.push(entity));
+
+        entities.retain(|entity| {
+            let Ok((entity, _transform, collider, layers)) = self.colliders.get(*entity) else {
+                return false;
+            };
+            return filter.accepts(entity, collider, layers);
+        });
+        return entities;
+    }
+
+    /// First collider hit by the ray within `max_distance`.
+    pub fn ray_cast(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<RayHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return None;
+        }
+        return self
+            .ray_hits(origin, direction, max_distance, filter)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance));
+    }
+
+    /// Every collider hit by the ray, nearest first.
+    pub fn ray_cast_all(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<RayHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return Vec::new();
+        }
+        let mut hits: Vec<RayHit> = self
+            .ray_hits(origin, direction, max_distance, filter)
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        return hits;
+    }
+
+    /// Unsorted hits of the ray along the normalized `direction`.
+    fn ray_hits(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> impl Iterator<Item = RayHit> + '_ {
+        let end = origin + direction * max_distance;
+        let ray_aabb = FlatAABB {
+            min: origin.min(end),
+            max: origin.max(end),
+        };
+        return self
+            .candidates(&ray_aabb, filter)
+            .into_iter()
+            .filter_map(move |entity| {
+                let (_, transform, collider, _) = self.colliders.get(entity).ok()?;
+                let (hit, sub_shape) =
+                    ray_cast_shape(&collider.shape, transform, origin, direction, max_distance)?;
+                return Some(RayHit {
+                    entity,
+                    distance: hit.distance,
+                    point: origin + direction * hit.distance,
+                    normal: hit.normal,
+                    sub_shape,
+                });
+            });
+    }
+
+    /// First collider hit by `shape` moved along `direction` within `max_distance`.
+    pub fn shape_cast(
+        &self,
+        shape: &Shape,
+        start: &Transform,
+        direction: Vec2,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<ShapeHit> {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return None;
+        }
+
+        let swept_aabb = shape.get_aabb(start).swept(direction * max_distance);
+        let mut first_hit: Option<ShapeHit> = None;
+        for entity in self.candidates(&swept_aabb, filter) {
+            let Ok((_, transform, collider, _)) = self.colliders.get(entity) else {
+                continue;
+            };
+            let Some((hit, sub_shape)) = cast_shape(
+                shape,
+                start,
+                direction,
+                max_distance,
+                &collider.shape,
+                transform,
+            ) else {
+                continue;
+            };
+            if first_hit.is_some_and(|first_hit| first_hit.distance <= hit.distance) {
+                continue;
+            }
+            first_hit = Some(ShapeHit {
+                entity,
+                distance: hit.distance,
+                point: hit.point,
+                normal: hit.normal,
+                sub_shape,
+            });
+        }
+        return first_hit;
+    }
+
+    /// Colliders containing `point`.
+    pub fn point_query(&self, point: Vec2, filter: &SpatialQueryFilter) -> Vec<Entity> {
+        let point_aabb = FlatAABB {
+            min: point,
+            max: point,
+        };
+        let mut entities = self.candidates(&point_aabb, filter);
+        entities.retain(|entity| {
+            let Ok((_, transform, collider, _)) = self.colliders.get(*entity) else {
+                return false;
+            };
+            return shape_contains_point(&collider.shape, transform, point);
+        });
+        return entities;
+    }
+
+    /// Colliders overlapping `shape` placed at `transform`, touching included.
+    pub fn overlap_shape(
+        &self,
+        shape: &Shape,
+        transform: &Transform,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<Entity> {
+        let mut entities = self.candidates(&shape.get_aabb(transform), filter);
+        entities.retain(|entity| {
+            let Ok((_, other_transform, collider, _)) = self.colliders.get(*entity) else {
+                return false;
+            };
+            return shape_distance(shape, transform, &collider.shape, other_transform)
+                .is_some_and(|(distance, _normal)| distance <= 0.);
+        });
+        return entities;
+    }
+
+    /// Colliders whose shape overlaps `aabb`, not only their bounding box.
+    pub fn overlap_aabb(&self, aabb: &FlatAABB, filter: &SpatialQueryFilter) -> Vec<Entity> {
+        let size = aabb.max - aabb.min;
+        let center = (aabb.min + aabb.max) * 0.5;
+        return self.overlap_shape(
+            &Shape::Box(BoxParams::new(size.x, size.y)),
+            &Transform::from_translation(center.extend(0.)),
+            filter,
+        );
+    }
+
+    /// Closest points between the colliders of two entities, `None` if they overlap.
+    pub fn closest_points(&self, entity_a: Entity, entity_b: Entity) -> Option<ClosestPoints> {
+        let (_, transform_a, collider_a, _) = self.colliders.get(entity_a).ok()?;
+        let (_, transform_b, collider_b, _) = self.colliders.get(entity_b).ok()?;
+        let closest = closest_points(
+            &collider_a.shape,
+            transform_a,
+            &collider_b.shape,
+            transform_b,
+        )?;
+        if closest.distance <= 0. {
+            return None;
+        }
+        return Some(closest);
+    }
+
+    /// Nearest surface point, colliders containing `point` come first.
+    pub fn project_point(
+        &self,
+        point: Vec2,
+        filter: &SpatialQueryFilter,
+    ) -> Option<PointProjectionHit> {
+        let mut search_size = PROJECT_POINT_SEARCH_SIZE;
+        let mut nearest: Option<PointProjectionHit> = None;
+        for _search in 0..MAX_PROJECT_POINT_SEARCHES {
+            let search_aabb = FlatAABB {
+                min: point - Vec2::splat(search_size),
+                max: point + Vec2::splat(search_size),
+            };
+            for entity in self.candidates(&search_aabb, filter) {
+                let Ok((_, transform, collider, _)) = self.colliders.get(entity) else {
+                    continue;
+                };
+                let Some(projection) = project_point_on_shape(&collider.shape, transform, point)
+                else {
+                    continue;
+                };
+                let is_nearer = match nearest {
+                    None => true,
+                    Some(nearest) if nearest.is_inside != projection.is_inside => {
+                        projection.is_inside
+                    }
+                    Some(nearest) => {
+                        projection.point.distance_squared(point)
+                            < nearest.point.distance_squared(point)
+                    }
+                };
+                if is_nearer {
+                    nearest = Some(PointProjectionHit {
+                        entity,
+                        point: projection.point,
+                        is_inside: projection.is_inside,
+                    });
+                }
+            }
+
+            let Some(nearest) = nearest else {
+                search_size *= 2.;
+                continue;
+            };
+            // colliders closer than the nearest one found so far overlap the
+            // box reaching out to it, search it once more if it is larger
+            let distance = nearest.point.distance(point);
+            if nearest.is_inside || distance <= search_size {
+                return Some(nearest);
+            }
+            search_size = distance;
+        }
+        return nearest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        flat_body::{
+            ChainParams, CircleParams, CompoundParams, FlatBody, FlatBodyType, on_flat_body_added,
+        },
+        flat_world::{insert_into_query_tree, refresh_query_tree},
+    };
+
+    fn world_with_colliders(colliders: Vec<(Vec2, Collider)>) -> (World, Vec<Entity>) {
+        let mut world = World::new();
+        world.insert_resource(FlatWorld::default());
+        world.add_observer(on_flat_body_added);
+        world.add_observer(insert_into_query_tree);
+        let entities = colliders
+            .into_iter()
+            .map(|(position, collider)| {
+                world
+                    .spawn((
+                        Transform::from_translation(position.extend(0.)),
+                        FlatBody::new(1., FlatBodyType::Static, 0.5),
+                        collider,
+                    ))
+                    .id()
+            })
+            .collect();
+        return (world, entities);
+    }
+
+    fn world_with_boxes(positions: &[Vec2]) -> (World, Vec<Entity>) {
+        return world_with_colliders(
+            positions
+                .iter()
+                .map(|position| {
+                    (
+                        *position,
+                        Collider::new(Shape::Box(BoxParams::new(100., 100.))),
+                    )
+                })
+                .collect(),
+        );
+    }
+
+    fn overlap_aabb(world: &mut World, min: Vec2, max: Vec2) -> HashSet<Entity> {
+        let entities = world
+            .run_system_once(move |spatial_query: SpatialQuery| {
+                spatial_query.overlap_aabb(&FlatAABB { min, max }, &SpatialQueryFilter::default())
+            })
+            .unwrap();
+        return entities.into_iter().collect();
+    }
+
+    #[test]
+    fn separated_boxes() {
+        let (mut world, entities) = world_with_boxes(&[Vec2::new(0., 0.), Vec2::new(130., 20.)]);
+        let (a, b) = (entities[0], entities[1]);
+
+        let closest = world
+            .run_system_once(move |spatial_query: SpatialQuery| spatial_query.closest_points(a, b))
+            .unwrap()
+            .expect("boxes are apart");
+        assert!((closest.distance - 30.).abs() < 1e-3);
+        assert!(closest.normal.distance(Vec2::X) < 1e-3);
+        assert!((closest.point_a.x - 50.).abs() < 1e-3);
+        assert!((closest.point_b.x - 80.).abs() < 1e-3);
+        // any point of the facing edges where they share a height is closest
+        assert!((closest.point_a.y - closest.point_b.y).abs() < 1e-3);
+        assert!((-30. ..=50.).contains(&closest.point_a.y));
+
+        assert!(overlap_aabb(&mut world, Vec2::new(55., -10.), Vec2::new(75., 10.)).is_empty());
+        assert_eq!(
+            overlap_aabb(&mut world, Vec2::new(40., 0.), Vec2::new(90., 10.)),
+            HashSet::from([a, b])
+        );
+        assert_eq!(
+            overlap_aabb(&mut world, Vec2::new(70., 60.), Vec2::new(90., 80.)),
+            HashSet::from([b])
+        );
+    }
+
+    #[test]
+    fn moved_bodies_are_found_after_refresh() {
+        let (mut world, entities) = world_with_boxes(&[Vec2::ZERO]);
+        world.run_system_once(refresh_query_tree).unwrap();
+        world
+            .get_mut::<Transform>(entities[0])
+            .unwrap()
+            .translation
+            .x = 500.;
+        world.run_system_once(refresh_query_tree).unwrap();
+
+        let near = |x: f32| (Vec2::new(x - 10., -10.), Vec2::new(x + 10., 10.));
+        let (min, max) = near(0.);
+        assert!(overlap_aabb(&mut world, min, max).is_empty());
+        let (min, max) = near(500.);
+        assert_eq!(
+            overlap_aabb(&mut world, min, max),
+            HashSet::from([entities[0]])
+        );
+    }
+
+    #[test]
+    fn overlapping_boxes() {
+        let (mut world, entities) = world_with_boxes(&[Vec2::new(0., 0.), Vec2::new(80., 0.)]);
+        let (a, b) = (entities[0], entities[1]);
+
+        let closest = world
+            .run_system_once(move |spatial_query: SpatialQuery| spatial_query.closest_points(a, b))
+            .unwrap();
+        assert!(closest.is_none());
+
+        let box_shape = Shape::Box(BoxParams::new(100., 100.));
+        let closest = closest_points(
+            &box_shape,
+            &Transform::IDENTITY,
+            &box_shape,
+            &Transform::from_xyz(80., 0., 0.),
+        )
+        .unwrap();
+        assert_eq!(closest.distance, 0.);
+        assert_eq!(
+            overlap_aabb(&mut world, Vec2::new(35., -5.), Vec2::new(45., 5.)),
+            HashSet::from([a, b])
+        );
+    }
+
+    #[test]
+    fn ray_cast_all_nearest_first() {
+        let (mut world, entities) =
+            world_with_boxes(&[Vec2::new(300., 0.), Vec2::new(0., 0.), Vec2::new(150., 0.)]);
+        let (far, near, middle) = (entities[0], entities[1], entities[2]);
+
+        let cast_all = |world: &mut World, max_distance: f32| {
+            return world
+                .run_system_once(move |spatial_query: SpatialQuery| {
+                    spatial_query.ray_cast_all(
+                        Vec2::new(-200., 0.),
+                        Vec2::X,
+                        max_distance,
+                        &SpatialQueryFilter::default(),
+                    )
+                })
+                .unwrap();
+        };
+        let hits = cast_all(&mut world, 1000.);
+        assert_eq!(
+            hits.iter().map(|hit| hit.entity).collect::<Vec<_>>(),
+            [near, middle, far]
+        );
+        for (hit, distance) in hits.iter().zip([150., 300., 450.]) {
+            assert!((hit.distance - distance).abs() < 1e-3);
+            assert_eq!(hit.normal, -Vec2::X);
+        }
+
+        let hits = cast_all(&mut world, 400.);
+        assert_eq!(
+            hits.iter().map(|hit| hit.entity).collect::<Vec<_>>(),
+            [near, middle]
+        );
+    }
+
+    /// World with a circle, a box, a chain and a sensor circle.
+    fn world_with_shapes() -> (World, Vec<Entity>) {
+        return world_with_colliders(vec![
+            (
+                Vec2::ZERO,
+                Collider::new(Shape::Circle(CircleParams::new(20.))),
+            ),
+            (
+                Vec2::new(100., 0.),
+                Collider::new(Shape::Box(BoxParams::new(40., 40.))),
+            ),
+            (
+                Vec2::ZERO,
+                Collider::new(Shape::Chain(ChainParams::new(vec![
+                    Vec2::new(-100., 100.),
+                    Vec2::new(0., 100.),
+                    Vec2::new(100., 150.),
+                ]))),
+            ),
+            (
+                Vec2::new(200., 0.),
+                Collider::sensor(Shape::Circle(CircleParams::new(10.))),
+            ),
+        ]);
+    }
+
+    fn point_query(world: &mut World, point: Vec2, filter: SpatialQueryFilter) -> HashSet<Entity> {
+        let entities = world
+            .run_system_once(move |spatial_query: SpatialQuery| {
+                spatial_query.point_query(point, &filter)
+            })
+            .unwrap();
+        return entities.into_iter().collect();
+    }
+
+    fn overlap_shape(
+        world: &mut World,
+        shape: Shape,
+        position: Vec2,
+        filter: SpatialQueryFilter,
+    ) -> HashSet<Entity> {
+        let entities = world
+            .run_system_once(move |spatial_query: SpatialQuery| {
+                let transform = Transform::from_translation(position.extend(0.));
+                spatial_query.overlap_shape(&shape, &transform, &filter)
+            })
+            .unwrap();
+        return entities.into_iter().collect();
+    }
+
+    #[test]
+    fn point_query_inside_on_edge_and_outside() {
+        let (mut world, entities) = world_with_shapes();
+        let (circle, square, chain) = (entities[0], entities[1], entities[2]);
+        let filter = SpatialQueryFilter::default;
+
+        for (point, expected) in [
+            (Vec2::ZERO, HashSet::from([circle])),
+            (Vec2::new(20., 0.), HashSet::from([circle])),
+            (Vec2::new(21., 0.), HashSet::new()),
+            (Vec2::new(100., 0.), HashSet::from([square])),
+            (Vec2::new(120., 5.), HashSet::from([square])),
+            (Vec2::new(121., 0.), HashSet::new()),
+            // the chain has no inside, only points on it
+            (Vec2::new(-50., 100.), HashSet::from([chain])),
+            (Vec2::new(50., 125.), HashSet::from([chain])),
+            (Vec2::new(-50., 99.), HashSet::new()),
+            (Vec2::new(50., 120.), HashSet::new()),
+        ] {
+            assert_eq!(
+                point_query(&mut world, point, filter()),
+                expected,
+                "at {point}"
+            );
+        }
+    }
+
+    #[test]
+    fn point_query_filter() {
+        let (mut world, entities) = world_with_shapes();
+        let (circle, sensor) = (entities[0], entities[3]);
+
+        let excluding = SpatialQueryFilter {
+            excluded_entities: HashSet::from([circle]),
+            ..Default::default()
+        };
+        assert!(point_query(&mut world, Vec2::ZERO, excluding).is_empty());
+
+        let sensor_point = Vec2::new(200., 0.);
+        assert!(point_query(&mut world, sensor_point, SpatialQueryFilter::default()).is_empty());
+        let with_sensors = SpatialQueryFilter {
+            include_sensors: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            point_query(&mut world, sensor_point, with_sensors),
+            HashSet::from([sensor])
+        );
+    }
+
+    #[test]
+    fn overlap_shape_circle_and_box() {
+        let (mut world, entities) = world_with_shapes();
+        let (circle, square, chain, sensor) = (entities[0], entities[1], entities[2], entities[3]);
+        let small_box = Shape::Box(BoxParams::new(10., 10.));
+        let small_circle = Shape::Circle(CircleParams::new(5.));
+        let filter = SpatialQueryFilter::default;
+
+        // resting on top of the circle counts, just above it does not
+        for (shape, position, expected) in [
+            (
+                small_box.clone(),
+                Vec2::new(0., 25.),
+                HashSet::from([circle]),
+            ),
+            (small_box.clone(), Vec2::new(0., 26.), HashSet::new()),
+            (
+                small_circle.clone(),
+                Vec2::new(0., 25.),
+                HashSet::from([circle]),
+            ),
+            (small_circle.clone(), Vec2::new(0., 26.), HashSet::new()),
+            (
+                Shape::Box(BoxParams::new(100., 10.)),
+                Vec2::new(60., 0.),
+                HashSet::from([circle, square]),
+            ),
+            (
+                small_circle.clone(),
+                Vec2::new(-50., 103.),
+                HashSet::from([chain]),
+            ),
+            (small_circle.clone(), Vec2::new(-50., 106.), HashSet::new()),
+        ] {
+            assert_eq!(
+                overlap_shape(&mut world, shape, position, filter()),
+                expected,
+                "at {position}"
+            );
+        }
+
+        let excluding = SpatialQueryFilter {
+            excluded_entities: HashSet::from([circle]),
+            ..Default::default()
+        };
+        assert_eq!(
+            overlap_shape(
+                &mut world,
+                Shape::Box(BoxParams::new(100., 10.)),
+                Vec2::new(60., 0.),
+                excluding
+            ),
+            HashSet::from([square])
+        );
+
+        let sensor_position = Vec2::new(200., 0.);
+        assert!(overlap_shape(&mut world, small_box.clone(), sensor_position, filter()).is_empty());
+        let with_sensors = SpatialQueryFilter {
+            include_sensors: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            overlap_shape(&mut world, small_box, sensor_position, with_sensors),
+            HashSet::from([sensor])
+        );
+    }
+
+    fn project_point(world: &mut World, point: Vec2) -> Option<PointProjectionHit> {
+        return world
+            .run_system_once(move |spatial_query: SpatialQuery| {
+                spatial_query.project_point(point, &SpatialQueryFilter::default())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn project_point_outside_and_inside() {
+        let (mut world, entities) = world_with_shapes();
+        let (circle, square) = (entities[0], entities[1]);
+
+        let hit = project_point(&mut world, Vec2::new(60., 0.)).unwrap();
+        assert_eq!(hit.entity, square);
+        assert!(!hit.is_inside);
+        assert!(
+            hit.point.distance(Vec2::new(80., 0.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+
+        let hit = project_point(&mut world, Vec2::new(5., 0.)).unwrap();
+        assert_eq!(hit.entity, circle);
+        assert!(hit.is_inside);
+        assert!(
+            hit.point.distance(Vec2::new(20., 0.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+
+        let hit = project_point(&mut world, Vec2::new(100., 15.)).unwrap();
+        assert_eq!(hit.entity, square);
+        assert!(hit.is_inside);
+        assert!(
+            hit.point.distance(Vec2::new(100., 20.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+    }
+
+    #[test]
+    fn project_point_beyond_first_search_box() {
+        // the diagonal circle is found first, the nearer one only by the
+        // search reaching out to it
+        let (mut world, entities) = world_with_colliders(vec![
+            (
+                Vec2::new(120., 120.),
+                Collider::new(Shape::Circle(CircleParams::new(5.))),
+            ),
+            (
+                Vec2::new(0., 150.),
+                Collider::new(Shape::Circle(CircleParams::new(5.))),
+            ),
+        ]);
+        let hit = project_point(&mut world, Vec2::ZERO).unwrap();
+        assert_eq!(hit.entity, entities[1]);
+        assert!(!hit.is_inside);
+        assert!(
+            hit.point.distance(Vec2::new(0., 145.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+
+        let (mut world, entities) = world_with_colliders(vec![(
+            Vec2::new(500., 0.),
+            Collider::new(Shape::Box(BoxParams::new(10., 10.))),
+        )]);
+        let hit = project_point(&mut world, Vec2::ZERO).unwrap();
+        assert_eq!(hit.entity, entities[0]);
+        assert!(
+            hit.point.distance(Vec2::new(495., 0.)) < 1e-3,
+            "{}",
+            hit.point
+        );
+    }
+
+    fn distance_to_outline(outline: &[Vec2], point: Vec2) -> f32 {
+        let mut distance = f32::MAX;
+        for i in 0..outline.len() {
+            let a = outline[i];
+            let edge = outline[(i + 1) % outline.len()] - a;
+            let t = ((point - a).dot(edge) / edge.length_squared()).clamp(0., 1.);
+            distance = distance.min(point.distance(a + edge * t));
+        }
+        return distance;
+    }
+
+    #[test]
+    fn project_point_skips_compound_seams() {
+        // bowl split into convex pieces, the seams run through its inside
+        let outline = [
+            Vec2::new(-120., 0.),
+            Vec2::new(120., 0.),
+            Vec2::new(120., 80.),
+            Vec2::new(90., 80.),
+            Vec2::new(90., 30.),
+            Vec2::new(-90., 30.),
+            Vec2::new(-90., 80.),
+            Vec2::new(-120., 80.),
+        ];
+        let compound_params = CompoundParams::from_concave_polygon(&outline).unwrap();
+        let (mut world, entities) = world_with_colliders(vec![(
+            compound_params.centroid,
+            Collider::new(Shape::Compound(compound_params)),
+        )]);
+
+        // next to where the posts meet the base, and all over the inside
+        let mut points = vec![Vec2::new(-95., 28.), Vec2::new(95., 28.)];
+        for x in (-115..=115).step_by(10) {
+            for y in (5..=75).step_by(10) {
+                let point = Vec2::new(x as f32, y as f32);
+                if point.y < 30. || point.x.abs() > 90. {
+                    points.push(point);
+                }
+            }
+        }
+        for point in points {
+            let hit = project_point(&mut world, point).unwrap();
+            assert_eq!(hit.entity, entities[0]);
+            assert!(hit.is_inside);
+            assert!(
+                distance_to_outline(&outline, hit.point) < 1e-2,
+                "{point} projected inside to {}",
+                hit.point
+            );
+            let expected = distance_to_outline(&outline, point);
+            assert!(
+                (hit.point.distance(point) - expected).abs() < 1e-2,
+                "{point} projected to {}, {expected} from the outline",
+                hit.point
+            );
+        }
+    }
+}
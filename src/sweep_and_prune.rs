@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{collisions::intersect_aabbs, flat_aabb::FlatAABB};
+
+/// Start or end of a body AABB on the x axis.
+struct Endpoint {
+    value: f32,
+    entity: Entity,
+    is_min: bool,
+}
+
+struct SweepProxy {
+    aabb: FlatAABB,
+    is_static: bool,
+}
+
+/// Sort and sweep broad phase along the x axis.
+#[derive(Default)]
+pub struct SweepAndPrune {
+    endpoints: Vec<Endpoint>,
+    proxies: HashMap<Entity, SweepProxy>,
+}
+
+impl SweepAndPrune {
+    pub fn update(&mut self, entity: Entity, aabb: FlatAABB, is_static: bool) {
+        if !self.proxies.contains_key(&entity) {
+            self.endpoints.push(Endpoint {
+                value: aabb.min.x,
+                entity,
+                is_min: true,
+            });
+            self.endpoints.push(Endpoint {
+                value: aabb.max.x,
+                entity,
+                is_min: false,
+            });
+        }
+        self.proxies.insert(entity, SweepProxy { aabb, is_static });
+    }
+
+    /// Removes every entity for which `keep` returns false.
+    pub fn retain(&mut self, keep: impl Fn(Entity) -> bool) {
+        self.proxies.retain(|entity, _| keep(*entity));
+        self.endpoints
+            .retain(|endpoint| self.proxies.contains_key(&endpoint.entity));
+    }
+
+    fn sort_endpoints(&mut self) {
+        for endpoint in self.endpoints.iter_mut() {
+            let aabb = &self.proxies[&endpoint.entity].aabb;
+            endpoint.value = if endpoint.is_min {
+                aabb.min.x
+            } else {
+                aabb.max.x
+            };
+        }
+
+        // ends go before starts at equal values, touching boxes do not collide
+        let goes_before = |a: &Endpoint, b: &Endpoint| {
+            a.value < b.value || (a.value == b.value && !a.is_min && b.is_min)
+        };
+        for i in 1..self.endpoints.len() {
+            let mut j = i;
+            while j > 0 && goes_before(&self.endpoints[j], &self.endpoints[j - 1]) {
+                self.endpoints.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+    }
+
+    /// Sorted pairs of overlapping non static entities accepted by `filter`.
+    pub fn find_pairs(&mut self, filter: impl Fn(Entity, Entity) -> bool) -> Vec<(Entity, Entity)> {
+        self.sort_endpoints();
+
+        let mut pairs = Vec::new();
+        let mut active: Vec<Entity> = Vec::new();
+        for endpoint in self.endpoints.iter() {
+            if !endpoint.is_min {
+                active.retain(|entity| *entity != endpoint.entity);
+                continue;
+            }
+
+            let proxy = &self.proxies[&endpoint.entity];
+            for other in active.iter() {
+                let other_proxy = &self.proxies[other];
+                if proxy.is_static && other_proxy.is_static {
+                    continue;
+                }
+                if !filter(endpoint.entity, *other) {
+                    continue;
+                }
+                if !intersect_aabbs(&proxy.aabb, &other_proxy.aabb) {
+                    continue;
+                }
+                pairs.push((endpoint.entity.min(*other), endpoint.entity.max(*other)));
+            }
+            active.push(endpoint.entity);
+        }
+
+        pairs.sort_unstable();
+        return pairs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{
+        collision_layers::CollisionLayers,
+        collisions::{Collider, Shape},
+        flat_body::{BoxParams, FlatBody, FlatBodyType},
+        flat_world::{BroadPhaseKind, FlatWorld, broad_phase, brute_force_broad_phase},
+    };
+
+    fn entity(index: u32) -> Entity {
+        return Entity::from_raw_u32(index).unwrap();
+    }
+
+    /// Every proxy has one start and one end, in order, and the list is sorted.
+    fn assert_sorted(sweep_and_prune: &SweepAndPrune) {
+        assert_eq!(
+            sweep_and_prune.endpoints.len(),
+            2 * sweep_and_prune.proxies.len()
+        );
+        for pair in sweep_and_prune.endpoints.windows(2) {
+            assert!(pair[0].value <= pair[1].value, "endpoints out of order");
+        }
+        for (entity, proxy) in sweep_and_prune.proxies.iter() {
+            let position = |is_min: bool| {
+                sweep_and_prune
+                    .endpoints
+                    .iter()
+                    .position(|endpoint| endpoint.entity == *entity && endpoint.is_min == is_min)
+                    .expect("proxy has both endpoints")
+            };
+            let (start, end) = (position(true), position(false));
+            assert!(start < end);
+            assert_eq!(sweep_and_prune.endpoints[start].value, proxy.aabb.min.x);
+            assert_eq!(sweep_and_prune.endpoints[end].value, proxy.aabb.max.x);
+        }
+    }
+
+    fn overlapping_pairs(aabbs: &[(Entity, FlatAABB)]) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for (i, (a, aabb_a)) in aabbs.iter().enumerate() {
+            for (b, aabb_b) in aabbs[i + 1..].iter() {
+                if intersect_aabbs(aabb_a, aabb_b) {
+                    pairs.push(((*a).min(*b), (*a).max(*b)));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    #[test]
+    fn endpoints_stay_sorted_as_bodies_cross() {
+        let mut sweep_and_prune = SweepAndPrune::default();
+        // a row of boxes, even ones move right and odd ones left through them
+        let mut aabbs: Vec<(Entity, FlatAABB)> = (0..10)
+            .map(|i| {
+                let x = 40. * i as f32;
+                (entity(i), FlatAABB::new(x, 0., x + 20., 20.))
+            })
+            .collect();
+
+        for step in 0..30 {
+            if step == 15 {
+                // every third box goes away
+                sweep_and_prune.retain(|entity| entity.index_u32() % 3 != 0);
+                aabbs.retain(|(entity, _aabb)| entity.index_u32() % 3 != 0);
+            }
+            for (entity, aabb) in aabbs.iter() {
+                sweep_and_prune.update(*entity, *aabb, false);
+            }
+
+            assert_eq!(
+                sweep_and_prune.find_pairs(|_a, _b| true),
+                overlapping_pairs(&aabbs),
+                "step {step}"
+            );
+            assert_sorted(&sweep_and_prune);
+
+            for (entity, aabb) in aabbs.iter_mut() {
+                let motion = if entity.index_u32() % 2 == 0 {
+                    13.
+                } else {
+                    -11.
+                };
+                aabb.min.x += motion;
+                aabb.max.x += motion;
+            }
+        }
+        assert!(
+            sweep_and_prune
+                .endpoints
+                .iter()
+                .all(|endpoint| endpoint.entity.index_u32() % 3 != 0)
+        );
+    }
+
+    /// Checks the broad phase selected in `FlatWorld` finds the brute force pairs.
+    fn assert_broad_phase_matches(world: &mut World, step: usize) {
+        world
+            .run_system_once(
+                move |mut query: Query<(Entity, &mut Transform, &mut FlatBody, &mut Collider)>,
+                      layers: Query<&CollisionLayers>,
+                      mut flat_world: ResMut<FlatWorld>| {
+                    let mut pairs = Vec::new();
+                    broad_phase(&mut query, &layers, &mut flat_world, &mut pairs, 1. / 60.);
+                    let mut expected = Vec::new();
+                    brute_force_broad_phase(&mut query, &layers, &mut expected, 0.);
+                    for pairs in [&mut pairs, &mut expected] {
+                        for pair in pairs.iter_mut() {
+                            *pair = (pair.0.min(pair.1), pair.0.max(pair.1));
+                        }
+                        pairs.sort_unstable();
+                    }
+                    assert_eq!(pairs, expected, "step {step}");
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn switching_broad_phase_mid_simulation() {
+        let mut world = World::new();
+        world.init_resource::<FlatWorld>();
+        world.spawn((
+            Transform::default(),
+            FlatBody::new(1., FlatBodyType::Static, 0.),
+            Collider::new(Shape::Box(BoxParams::new(1000., 20.))),
+        ));
+        let bodies: Vec<Entity> = (0..8)
+            .map(|i| {
+                world
+                    .spawn((
+                        Transform::from_xyz(-350. + 100. * i as f32, 20., 0.),
+                        FlatBody::new(1., FlatBodyType::Dynamic, 0.),
+                        Collider::new(Shape::Box(BoxParams::new(30., 30.))),
+                    ))
+                    .id()
+            })
+            .collect();
+
+        let kinds = [
+            BroadPhaseKind::SweepAndPrune,
+            BroadPhaseKind::DynamicTree,
+            BroadPhaseKind::SpatialHash,
+            BroadPhaseKind::BruteForce,
+        ];
+        for step in 0..24 {
+            world.resource_mut::<FlatWorld>().broad_phase = kinds[(step / 3) % kinds.len()];
+            // removed while the sweep is not running
+            if step == 4 {
+                world.despawn(bodies[2]);
+            }
+            for (i, body) in bodies.iter().enumerate() {
+                let Some(mut transform) = world.get_mut::<Transform>(*body) else {
+                    continue;
+                };
+                // bodies pass each other and rise off the floor over time
+                let direction = if i % 2 == 0 { 1. } else { -1. };
+                transform.translation.x += direction * 23.;
+                transform.translation.y += 0.5;
+            }
+
+            assert_broad_phase_matches(&mut world, step);
+
+            let sweep_and_prune = &world.resource::<FlatWorld>().sweep_and_prune;
+            if !sweep_and_prune.proxies.is_empty() {
+                assert_sorted(sweep_and_prune);
+            }
+        }
+        // the floor and the seven bodies left
+        let sweep_and_prune = &world.resource::<FlatWorld>().sweep_and_prune;
+        assert_eq!(sweep_and_prune.proxies.len(), 8);
+        assert!(!sweep_and_prune.proxies.contains_key(&bodies[2]));
+    }
+}